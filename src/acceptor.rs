@@ -0,0 +1,118 @@
+use std::io;
+
+use compio::io::{AsyncRead, AsyncWrite};
+use compio::runtime::JoinHandle;
+use foreign_types::ForeignTypeRef;
+use openssl::error::ErrorStack;
+use openssl::pkey::PKey;
+use openssl::ssl::{Ssl, SslAcceptor, SslMethod, SslRef};
+use openssl::x509::X509;
+
+use crate::{HandshakeError, SslStream, ffi};
+
+/// Builds an acceptor from an in-memory certificate chain and private key, both PEM encoded.
+///
+/// `cert_pem` holds the server certificate first, followed by any intermediates to send along. The context
+/// follows Mozilla's modern recommendations, i.e. accepts TLS 1.3 only, see
+/// [`SslAcceptor::mozilla_modern_v5`]. Fails if either input is malformed or the key does not match the
+/// certificate.
+pub fn acceptor_from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<TlsAcceptor, ErrorStack> {
+    // fails without any certificate, unlike `stack_from_pem`
+    let cert = X509::from_pem(cert_pem)?;
+    let key = PKey::private_key_from_pem(key_pem)?;
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server())?;
+    builder.set_certificate(&cert)?;
+    for intermediate in X509::stack_from_pem(cert_pem)?.into_iter().skip(1) {
+        builder.add_extra_chain_cert(intermediate)?;
+    }
+    builder.set_private_key(&key)?;
+    builder.check_private_key()?;
+    Ok(TlsAcceptor::new(builder.build()))
+}
+
+/// A wrapper around [`SslAcceptor`] accepting compio streams.
+///
+/// Besides the context shared by all connections, it holds settings applied to every accepted [`Ssl`].
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: SslAcceptor,
+    max_cert_list: Option<u32>,
+}
+
+impl TlsAcceptor {
+    /// Create a new `TlsAcceptor` from a configured [`SslAcceptor`].
+    pub fn new(acceptor: SslAcceptor) -> TlsAcceptor {
+        TlsAcceptor {
+            acceptor,
+            max_cert_list: None,
+        }
+    }
+
+    /// Returns a shared reference to the inner [`SslAcceptor`].
+    #[inline(always)]
+    pub fn acceptor(&self) -> &SslAcceptor {
+        &self.acceptor
+    }
+
+    /// Limits the size of the certificate chain the peer may send during the handshake.
+    ///
+    /// OpenSSL buffers the whole chain before verifying it, so an unbounded limit lets a malicious client make
+    /// the server allocate large amounts of memory. A handshake exceeding the limit fails with an
+    /// `excessive message size` error. OpenSSL's default is 100 KiB.
+    ///
+    /// Other handshake messages are bounded by OpenSSL itself, and the record size by the protocol.
+    ///
+    /// Reference: [`SSL_set_max_cert_list`](https://docs.openssl.org/master/man3/SSL_CTX_set_max_cert_list/)
+    pub fn set_max_cert_list(&mut self, size: u32) {
+        self.max_cert_list = Some(size);
+    }
+
+    /// Accepts a client connection, performing the server-side TLS handshake.
+    pub async fn accept<S: AsyncRead + AsyncWrite>(
+        &self,
+        stream: S,
+    ) -> Result<SslStream<S>, HandshakeError<S>> {
+        let mut ssl = Ssl::new(self.acceptor.context())?;
+        self.configure(&mut ssl);
+        let mut stream = SslStream::new(ssl, stream)?;
+        match stream.accept().await {
+            Ok(()) => Ok(stream),
+            Err(e) => Err(HandshakeError::Failure(stream, e)),
+        }
+    }
+
+    /// Spawns [`accept`](Self::accept) on the current compio runtime, returning a handle to await the stream.
+    ///
+    /// An accept loop can go on with the next connection right away, while slow or stalled clients complete
+    /// their handshakes in the background. The task owns `stream` and a clone of the acceptor, hence the
+    /// `'static` bound: `S` cannot borrow from the loop. Dropping the handle cancels the handshake and drops
+    /// the stream, closing the connection, so awaiting the handle is cancel safe. Awaiting it returns `Err` if
+    /// the task panicked.
+    pub fn spawn_accept<S>(&self, stream: S) -> JoinHandle<io::Result<SslStream<S>>>
+    where
+        S: AsyncRead + AsyncWrite + 'static,
+    {
+        let acceptor = self.clone();
+        compio::runtime::spawn(async move { Ok(acceptor.accept(stream).await?) })
+    }
+
+    fn configure(&self, ssl: &mut SslRef) {
+        if let Some(size) = self.max_cert_list {
+            // SAFETY: `ssl` is a valid pointer and this control takes no pointer argument
+            unsafe {
+                ffi::SSL_ctrl(
+                    ssl.as_ptr(),
+                    ffi::SSL_CTRL_SET_MAX_CERT_LIST,
+                    size as _,
+                    std::ptr::null_mut(),
+                );
+            }
+        }
+    }
+}
+
+impl From<SslAcceptor> for TlsAcceptor {
+    fn from(value: SslAcceptor) -> Self {
+        TlsAcceptor::new(value)
+    }
+}
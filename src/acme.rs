@@ -0,0 +1,71 @@
+use openssl::asn1::{Asn1Object, Asn1OctetString};
+use openssl::error::ErrorStack;
+use openssl::hash::{MessageDigest, hash};
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{AlpnError, NameType, SslContextBuilder};
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509, X509Extension};
+
+use crate::parse_alpn;
+use crate::self_signed::self_signed;
+
+/// The ALPN protocol of the ACME `tls-alpn-01` challenge.
+///
+/// Reference: [RFC 8737](https://www.rfc-editor.org/rfc/rfc8737)
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// The `id-pe-acmeIdentifier` extension holding the key authorization digest.
+const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+
+/// Registers a server-side ALPN selection function answering ACME `tls-alpn-01` challenges in-band.
+///
+/// A client offering [`ACME_TLS_ALPN`] is a validation server: it is presented the certificate `challenge`
+/// returns for the requested server name, typically one built with [`acme_tls_alpn_cert`], and the handshake
+/// fails with `no_application_protocol` if there is none. The connection must be closed after the handshake,
+/// as it carries no application data. Any other client is handled by `select`, just as with
+/// [`set_alpn_select`](crate::set_alpn_select).
+///
+/// The certificate is swapped while OpenSSL processes the ClientHello, before it picks the certificate to
+/// send, so no separate listener or context is needed.
+pub fn set_acme_alpn_select<C, F>(builder: &mut SslContextBuilder, challenge: C, select: F)
+where
+    C: Fn(&str) -> Option<(X509, PKey<Private>)> + 'static + Sync + Send,
+    F: for<'a> Fn(&[&'a [u8]]) -> Option<&'a [u8]> + 'static + Sync + Send,
+{
+    builder.set_alpn_select_callback(move |ssl, client| {
+        let offered = parse_alpn(client).ok_or(AlpnError::ALERT_FATAL)?;
+        if !offered.contains(&ACME_TLS_ALPN) {
+            return select(&offered).ok_or(AlpnError::NOACK);
+        }
+        let domain = ssl.servername(NameType::HOST_NAME).ok_or(AlpnError::ALERT_FATAL)?;
+        let (cert, key) = challenge(domain).ok_or(AlpnError::ALERT_FATAL)?;
+        ssl.set_certificate(&cert).map_err(|_| AlpnError::ALERT_FATAL)?;
+        ssl.set_private_key(&key).map_err(|_| AlpnError::ALERT_FATAL)?;
+        Ok(ACME_TLS_ALPN)
+    });
+}
+
+/// Builds the self-signed certificate answering the ACME `tls-alpn-01` challenge for `domain`.
+///
+/// `key_authorization` is the token of the challenge joined with the account key thumbprint, as computed by
+/// the ACME client. The certificate carries its SHA-256 digest in the critical `acmeIdentifier` extension,
+/// and is valid for a week, plenty for the validation.
+///
+/// Reference: [RFC 8737, section 3](https://www.rfc-editor.org/rfc/rfc8737#section-3)
+pub fn acme_tls_alpn_cert(
+    domain: &str,
+    key_authorization: &str,
+) -> Result<(X509, PKey<Private>), ErrorStack> {
+    self_signed(domain, 7, |builder| {
+        let san = SubjectAlternativeName::new().dns(domain).build(&builder.x509v3_context(None, None))?;
+        builder.append_extension(san)?;
+
+        // the DER encoding of an OCTET STRING holding the digest
+        let digest = hash(MessageDigest::sha256(), key_authorization.as_bytes())?;
+        let mut value = vec![0x04, digest.len() as u8];
+        value.extend_from_slice(&digest);
+        let oid = Asn1Object::from_str(ACME_IDENTIFIER_OID)?;
+        let value = Asn1OctetString::new_from_bytes(&value)?;
+        builder.append_extension(X509Extension::new_from_der(&oid, true, &value)?)
+    })
+}
@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::fmt;
+
+use openssl::ssl;
+
+use crate::ffi;
+
+/// A TLS alert, received from the peer or to be sent with [`SslStream::send_alert`](crate::SslStream::send_alert).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Alert(u8);
+
+impl Alert {
+    /// Creates an alert from its description code, e.g. 49 for `access_denied`.
+    #[inline(always)]
+    pub const fn new(code: u8) -> Alert {
+        Alert(code)
+    }
+
+    /// Returns the alert description code.
+    #[inline(always)]
+    pub fn code(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the name of the alert description as in the RFCs, e.g. `handshake_failure`.
+    pub fn description(self) -> &'static str {
+        match self.0 {
+            0 => "close_notify",
+            10 => "unexpected_message",
+            20 => "bad_record_mac",
+            21 => "decryption_failed",
+            22 => "record_overflow",
+            30 => "decompression_failure",
+            40 => "handshake_failure",
+            41 => "no_certificate",
+            42 => "bad_certificate",
+            43 => "unsupported_certificate",
+            44 => "certificate_revoked",
+            45 => "certificate_expired",
+            46 => "certificate_unknown",
+            47 => "illegal_parameter",
+            48 => "unknown_ca",
+            49 => "access_denied",
+            50 => "decode_error",
+            51 => "decrypt_error",
+            60 => "export_restriction",
+            70 => "protocol_version",
+            71 => "insufficient_security",
+            80 => "internal_error",
+            86 => "inappropriate_fallback",
+            90 => "user_canceled",
+            100 => "no_renegotiation",
+            109 => "missing_extension",
+            110 => "unsupported_extension",
+            111 => "certificate_unobtainable",
+            112 => "unrecognized_name",
+            113 => "bad_certificate_status_response",
+            114 => "bad_certificate_hash_value",
+            115 => "unknown_psk_identity",
+            116 => "certificate_required",
+            120 => "no_application_protocol",
+            _ => "unknown",
+        }
+    }
+
+    /// Extracts the alert received from the peer out of the error stack, if any.
+    pub(crate) fn from_error(err: &ssl::Error) -> Option<Alert> {
+        // OpenSSL reports received alerts as reasons offset by `SSL_AD_REASON_OFFSET`
+        err.ssl_error()?
+            .errors()
+            .iter()
+            .filter(|e| e.library_code() == ffi::ERR_LIB_SSL)
+            .find_map(|e| u8::try_from(e.reason_code() - ffi::SSL_AD_REASON_OFFSET).ok().map(Alert))
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// An error caused by a fatal alert of the peer.
+#[derive(Debug)]
+pub(crate) struct PeerAlertError {
+    pub(crate) alert: Alert,
+    pub(crate) error: ssl::Error,
+}
+
+impl fmt::Display for PeerAlertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "peer alert: {}", self.alert)
+    }
+}
+
+impl Error for PeerAlertError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        // `ssl::Error` would display its error stack and return it as its source as well
+        match self.error.ssl_error() {
+            Some(stack) => Some(stack),
+            None => Some(&self.error),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+
+use compio::io::{AsyncRead, AsyncWrite};
+use compio::runtime::Runtime;
+
+use crate::SslStream;
+
+/// A blocking [`std::io::Read`] and [`std::io::Write`] view of a stream, created by
+/// [`SslStream::into_blocking`].
+///
+/// Meant as glue for synchronous libraries while migrating to compio, not for production paths: every call
+/// blocks the thread on the current compio runtime until it completes, which drives the runtime's other tasks
+/// in the meantime, but nothing else runs on the thread. Writes behave as those of the stream, including
+/// [`set_write_buffer_threshold`](SslStream::set_write_buffer_threshold) and
+/// [`set_flush_on_write`](SslStream::set_flush_on_write), without copying the data into an owned buffer first.
+///
+/// # Panics
+///
+/// Every operation panics if the thread has no current compio runtime, i.e. outside of
+/// [`Runtime::enter`] or [`Runtime::block_on`]. It must not be called from an async task either, as the
+/// runtime cannot block on a future while polling another.
+#[derive(Debug)]
+pub struct BlockingStream<S> {
+    stream: SslStream<S>,
+}
+
+impl<S> BlockingStream<S> {
+    pub(crate) fn new(stream: SslStream<S>) -> BlockingStream<S> {
+        BlockingStream { stream }
+    }
+
+    /// Returns a shared reference to the stream.
+    pub fn get_ref(&self) -> &SslStream<S> {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the stream.
+    pub fn get_mut(&mut self) -> &mut SslStream<S> {
+        &mut self.stream
+    }
+
+    /// Consumes the adapter, returning the stream.
+    pub fn into_inner(self) -> SslStream<S> {
+        self.stream
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    Runtime::with_current(|runtime| runtime.block_on(future))
+}
+
+impl<S: AsyncRead + AsyncWrite> io::Read for BlockingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        block_on(self.stream.read_slice(buf))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> io::Write for BlockingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        block_on(self.stream.write_slice(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        block_on(AsyncWrite::flush(&mut self.stream))
+    }
+}
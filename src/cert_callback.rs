@@ -0,0 +1,79 @@
+use std::ffi::{c_int, c_void};
+use std::sync::OnceLock;
+
+use foreign_types::ForeignTypeRef;
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::ssl::{SslContext, SslContextBuilder, SslRef};
+
+use crate::{client_hello, ffi};
+
+type CertCallback = Box<dyn Fn(&mut SslRef) -> Result<CertResponse, ErrorStack> + Send + Sync>;
+
+fn callback_index() -> Index<SslContext, CertCallback> {
+    static INDEX: OnceLock<Index<SslContext, CertCallback>> = OnceLock::new();
+    *INDEX.get_or_init(|| SslContext::new_ex_index().expect("failed to allocate ex data index"))
+}
+
+/// The outcome of a certificate callback registered with [`set_cert_callback`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertResponse(c_int);
+
+impl CertResponse {
+    /// Continue the handshake with the certificate set on the `Ssl`.
+    pub const SUCCESS: CertResponse = CertResponse(1);
+
+    /// Return from the handshake with `SSL_ERROR_WANT_X509_LOOKUP`, see [`pause_cert_lookup`].
+    pub const RETRY: CertResponse = CertResponse(-1);
+}
+
+/// Registers a callback choosing the certificate of each handshake, e.g. by the requested host name.
+///
+/// On a server, `callback` runs once the ClientHello has been processed, so the SNI host name and the
+/// offered signature algorithms are known, and sets the certificate and key with
+/// [`SslRef::set_certificate`] and [`SslRef::set_private_key`]. On a client, it runs when the server asks
+/// for a certificate. Returning an error aborts the handshake.
+///
+/// To look the certificate up asynchronously, e.g. from a remote key management service, the callback
+/// returns the [`CertResponse::RETRY`] of [`pause_cert_lookup`].
+///
+/// Reference: [`SSL_CTX_set_cert_cb`](https://docs.openssl.org/master/man3/SSL_CTX_set_cert_cb/)
+pub fn set_cert_callback<F>(builder: &mut SslContextBuilder, callback: F)
+where
+    F: Fn(&mut SslRef) -> Result<CertResponse, ErrorStack> + Send + Sync + 'static,
+{
+    builder.set_ex_data(callback_index(), Box::new(callback) as CertCallback);
+    // SAFETY: the context is valid, and the callback only reads the closure stored in it
+    unsafe { ffi::SSL_CTX_set_cert_cb(builder.as_ptr(), Some(cert_cb), std::ptr::null_mut()) };
+}
+
+/// Pauses the handshake from within a [`set_cert_callback`] callback until `resume` completes.
+///
+/// The callback should return the [`CertResponse::RETRY`] given back, on which OpenSSL reports
+/// `SSL_ERROR_WANT_X509_LOOKUP`. [`SslStream::accept`](crate::SslStream::accept) then awaits `resume` and
+/// retries the handshake, invoking the callback once more. By then the certificate should be available to
+/// the callback, typically through shared state `resume` has filled, e.g. attached with
+/// [`set_ex_data`](crate::set_ex_data), so it can set it and return [`CertResponse::SUCCESS`].
+pub fn pause_cert_lookup<F>(ssl: &mut SslRef, resume: F) -> CertResponse
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    client_hello::set_resume(ssl, resume);
+    CertResponse::RETRY
+}
+
+unsafe extern "C" fn cert_cb(ssl: *mut ffi::SSL, _arg: *mut c_void) -> c_int {
+    // SAFETY: OpenSSL passes the valid `SSL` of the handshake, exclusively for the callback
+    let ssl = unsafe { SslRef::from_ptr_mut(ssl) };
+    let ctx = ssl.ssl_context().to_owned();
+    let Some(callback) = ctx.ex_data(callback_index()) else {
+        return 0;
+    };
+    match callback(ssl) {
+        Ok(response) => response.0,
+        Err(e) => {
+            e.put();
+            0
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::ffi::c_int;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+
+use foreign_types::ForeignTypeRef;
+use openssl::ex_data::Index;
+use openssl::ssl::{ClientHelloResponse, Ssl, SslContext, SslContextBuilder, SslRef};
+
+use crate::{ffi, parse_alpn};
+
+const TLSEXT_TYPE_SERVER_NAME: u32 = 0;
+const TLSEXT_TYPE_ALPN: u32 = 16;
+const TLSEXT_TYPE_SUPPORTED_VERSIONS: u32 = 43;
+
+type ResumeFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+fn resume_index() -> Index<Ssl, Mutex<Option<ResumeFuture>>> {
+    static INDEX: OnceLock<Index<Ssl, Mutex<Option<ResumeFuture>>>> = OnceLock::new();
+    *INDEX.get_or_init(|| Ssl::new_ex_index().expect("failed to allocate ex data index"))
+}
+
+fn peek_index() -> Index<Ssl, bool> {
+    static INDEX: OnceLock<Index<Ssl, bool>> = OnceLock::new();
+    *INDEX.get_or_init(|| Ssl::new_ex_index().expect("failed to allocate ex data index"))
+}
+
+fn peek_enabled_index() -> Index<SslContext, ()> {
+    static INDEX: OnceLock<Index<SslContext, ()>> = OnceLock::new();
+    *INDEX.get_or_init(|| SslContext::new_ex_index().expect("failed to allocate ex data index"))
+}
+
+/// What a client offered in its ClientHello, returned by
+/// [`SslStream::peek_client_hello`](crate::SslStream::peek_client_hello).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHelloInfo {
+    /// The host name requested with SNI, if any.
+    pub server_name: Option<String>,
+    /// The ALPN protocols offered, in the client's order.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// The TLS versions offered, see [`client_hello_versions`].
+    pub versions: Vec<u16>,
+}
+
+/// Enables [`SslStream::peek_client_hello`](crate::SslStream::peek_client_hello) for servers using the context.
+///
+/// This installs a client hello callback, replacing any other. Handshakes without a peek are not affected.
+///
+/// Reference: [`SslContextBuilder::set_client_hello_callback`](openssl::ssl::SslContextBuilder::set_client_hello_callback)
+pub fn enable_client_hello_peek(builder: &mut SslContextBuilder) {
+    builder.set_ex_data(peek_enabled_index(), ());
+    builder.set_client_hello_callback(|ssl, _| match ssl.ex_data_mut(peek_index()) {
+        // stop right after the ClientHello was read, the next call continues
+        Some(peek) if *peek => {
+            *peek = false;
+            Ok(ClientHelloResponse::RETRY)
+        }
+        _ => Ok(ClientHelloResponse::SUCCESS),
+    });
+}
+
+/// Makes the client hello callback pause the handshake once, returning `false` if the context lacks it.
+pub(crate) fn request_peek(ssl: &mut SslRef) -> bool {
+    if ssl.ssl_context().ex_data(peek_enabled_index()).is_none() {
+        return false;
+    }
+    ssl.set_ex_data(peek_index(), true);
+    true
+}
+
+/// Collects the ClientHello of a handshake paused in the client hello callback.
+pub(crate) fn client_hello_info(ssl: &SslRef) -> ClientHelloInfo {
+    // a list of names, each a one byte type, 0 for host names, and a two byte length
+    let server_name = client_hello_ext(ssl, TLSEXT_TYPE_SERVER_NAME).and_then(|ext| {
+        let list = ext.get(2..)?;
+        let (&kind, rest) = list.split_first()?;
+        if kind != 0 {
+            return None;
+        }
+        let len = u16::from_be_bytes(rest.get(..2)?.try_into().unwrap()) as usize;
+        String::from_utf8(rest.get(2..2 + len)?.to_vec()).ok()
+    });
+    let alpn_protocols = client_hello_ext(ssl, TLSEXT_TYPE_ALPN)
+        .and_then(|ext| parse_alpn(ext.get(2..)?))
+        .map(|protos| protos.into_iter().map(<[u8]>::to_vec).collect())
+        .unwrap_or_default();
+    ClientHelloInfo {
+        server_name,
+        alpn_protocols,
+        versions: client_hello_versions(ssl).unwrap_or_default(),
+    }
+}
+
+/// Pauses the handshake from within a client hello callback until `resume` completes.
+///
+/// The callback should return the [`ClientHelloResponse::RETRY`] given back, on which OpenSSL reports
+/// `SSL_ERROR_WANT_CLIENT_HELLO_CB`. [`SslStream::accept`](crate::SslStream::accept) then awaits `resume` and continues the handshake,
+/// invoking the callback once more. By then the outcome of the asynchronous work (e.g. a certificate looked
+/// up remotely) should be available to the callback, typically through shared state `resume` has filled.
+///
+/// Reference: [`SslContextBuilder::set_client_hello_callback`](openssl::ssl::SslContextBuilder::set_client_hello_callback)
+pub fn pause_handshake<F>(ssl: &mut SslRef, resume: F) -> ClientHelloResponse
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    set_resume(ssl, resume);
+    ClientHelloResponse::RETRY
+}
+
+/// Registers the future the handshake awaits before retrying a paused callback.
+pub(crate) fn set_resume<F>(ssl: &mut SslRef, resume: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    ssl.set_ex_data(resume_index(), Mutex::new(Some(Box::pin(resume))));
+}
+
+/// Takes the future registered by [`pause_handshake`], if any.
+pub(crate) fn take_resume(ssl: &mut SslRef) -> Option<ResumeFuture> {
+    ssl.ex_data_mut(resume_index()).and_then(|r| r.get_mut().unwrap().take())
+}
+
+/// Returns the cipher suites offered by the client, as IANA code points in the client's order.
+///
+/// Like the other `client_hello_*` functions, this is meant for fingerprinting, e.g. JA3, and is only
+/// available from within a client hello callback. Otherwise, `None` is returned.
+///
+/// Reference: [`SslRef::client_hello_ciphers`]
+pub fn client_hello_ciphers(ssl: &SslRef) -> Option<Vec<u16>> {
+    ssl.client_hello_ciphers().map(parse_u16s)
+}
+
+/// Returns the TLS versions offered by the client, as code points in the client's order, e.g. `0x0304` for
+/// TLS 1.3.
+///
+/// These are the `supported_versions` extension, or the legacy version field if the client did not send it.
+/// Only available from within a client hello callback, see [`client_hello_ciphers`].
+///
+/// Reference: [`SSL_client_hello_get0_ext`](https://docs.openssl.org/master/man3/SSL_CTX_set_client_hello_cb/)
+pub fn client_hello_versions(ssl: &SslRef) -> Option<Vec<u16>> {
+    if let Some(ext) = client_hello_ext(ssl, TLSEXT_TYPE_SUPPORTED_VERSIONS) {
+        // a one byte length, then the versions
+        return ext.split_first().map(|(_, versions)| parse_u16s(versions));
+    }
+    // SAFETY: `ssl` is valid
+    match unsafe { ffi::SSL_client_hello_get0_legacy_version(ssl.as_ptr()) } {
+        0 => None,
+        version => Some(vec![version as u16]),
+    }
+}
+
+/// Returns the types of the extensions sent by the client, in the client's order.
+///
+/// Only available from within a client hello callback, see [`client_hello_ciphers`].
+///
+/// Reference: [`SSL_client_hello_get1_extensions_present`](https://docs.openssl.org/master/man3/SSL_CTX_set_client_hello_cb/)
+pub fn client_hello_extensions(ssl: &SslRef) -> Option<Vec<u16>> {
+    let mut out: *mut c_int = std::ptr::null_mut();
+    let mut len = 0;
+    // SAFETY: `out` and `len` are valid out pointers, only written on success
+    let ret = unsafe { ffi::SSL_client_hello_get1_extensions_present(ssl.as_ptr(), &mut out, &mut len) };
+    if ret != 1 {
+        return None;
+    }
+    if out.is_null() {
+        return Some(Vec::new());
+    }
+    // SAFETY: OpenSSL allocated `len` ints, which we free right after copying them
+    let types = unsafe { std::slice::from_raw_parts(out, len) }.iter().map(|&t| t as u16).collect();
+    unsafe { ffi::OPENSSL_free(out.cast()) };
+    Some(types)
+}
+
+/// Returns the body of the extension `ty` of the ClientHello, if sent.
+fn client_hello_ext(ssl: &SslRef, ty: u32) -> Option<&[u8]> {
+    let mut ext = std::ptr::null();
+    let mut len = 0;
+    // SAFETY: `ext` and `len` are valid out pointers, only written on success
+    let ret = unsafe { ffi::SSL_client_hello_get0_ext(ssl.as_ptr(), ty, &mut ext, &mut len) };
+    // SAFETY: `ext` points into the client hello, which is kept as long as the handshake is in the callback
+    (ret == 1).then(|| unsafe { std::slice::from_raw_parts(ext, len) })
+}
+
+fn parse_u16s(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect()
+}
@@ -0,0 +1,293 @@
+use std::error::Error;
+use std::fmt;
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+
+use compio::BufResult;
+use compio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use compio::net::{TcpStream, ToSocketAddrsAsync};
+use openssl::error::ErrorStack;
+use openssl::nid::Nid;
+use openssl::ssl::{SslConnector, SslMethod};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509, X509Ref};
+
+use crate::{HandshakeError, SslStream, ffi};
+
+/// How long a connection attempt may be pending before the next address is tried, as recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Builds a connector trusting only the CA certificates in `ca_pem`, PEM encoded, instead of the system's.
+///
+/// Meant for internal services and tests with a private CA, or a self-signed server certificate, which can
+/// be passed as is. Fails if `ca_pem` is malformed or holds no certificate.
+pub fn connector_from_ca_pem(ca_pem: &[u8]) -> Result<TlsConnector, ErrorStack> {
+    let mut store = X509StoreBuilder::new()?;
+    // fails without any certificate, unlike `stack_from_pem`
+    store.add_cert(X509::from_pem(ca_pem)?)?;
+    for cert in X509::stack_from_pem(ca_pem)?.into_iter().skip(1) {
+        store.add_cert(cert)?;
+    }
+    let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+    builder.set_verify_cert_store(store.build())?;
+    Ok(TlsConnector::new(builder.build()))
+}
+
+/// A wrapper around [`SslConnector`] connecting compio streams.
+#[derive(Clone)]
+pub struct TlsConnector {
+    connector: SslConnector,
+}
+
+impl TlsConnector {
+    /// Create a new `TlsConnector` from a configured [`SslConnector`].
+    pub fn new(connector: SslConnector) -> TlsConnector {
+        TlsConnector { connector }
+    }
+
+    /// Returns a shared reference to the inner [`SslConnector`].
+    #[inline(always)]
+    pub fn connector(&self) -> &SslConnector {
+        &self.connector
+    }
+
+    /// Initiates a client-side TLS handshake, verifying that the peer certificate is valid for `domain`.
+    ///
+    /// `domain` is also used for SNI. If the certificate chain is trusted but issued for another name,
+    /// [`ConnectError::HostnameMismatch`] is returned.
+    ///
+    /// A verify callback configured on the [`SslConnector`] is not invoked, the verify mode is kept.
+    pub async fn connect<S: AsyncRead + AsyncWrite>(
+        &self,
+        domain: &str,
+        stream: S,
+    ) -> Result<SslStream<S>, ConnectError<S>> {
+        let mut ssl = self.connector.configure()?.into_ssl(domain)?;
+        ssl.param_mut().set_host(domain)?;
+
+        // OpenSSL drops the peer certificate on verification failure, so record its names on the way
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let captured = names.clone();
+        let mode = ssl.verify_mode();
+        ssl.set_verify_callback(mode, move |preverify, ctx| {
+            if ctx.error().as_raw() == ffi::X509_V_ERR_HOSTNAME_MISMATCH
+                && let Some(cert) = ctx.current_cert()
+            {
+                *captured.lock().unwrap() = cert_names(cert);
+            }
+            preverify
+        });
+
+        let mut stream = SslStream::new(ssl, stream)?;
+        match stream.connect().await {
+            Ok(()) => Ok(stream),
+            Err(_) if stream.ssl().verify_result().as_raw() == ffi::X509_V_ERR_HOSTNAME_MISMATCH => {
+                let cert_names = std::mem::take(&mut *names.lock().unwrap());
+                Err(ConnectError::HostnameMismatch {
+                    expected: domain.to_owned(),
+                    cert_names,
+                })
+            }
+            Err(e) => Err(ConnectError::Handshake(HandshakeError::Failure(stream, e))),
+        }
+    }
+
+    /// Resolves `host`, connects to it over TCP and performs the TLS handshake, verifying the peer certificate
+    /// for `host` just as [`connect`](TlsConnector::connect).
+    ///
+    /// On dual-stack networks, a broken path of one address family would stall the connection until the TCP
+    /// timeout. The addresses are therefore raced as described by RFC 8305 (Happy Eyeballs): they are tried in
+    /// the order of the resolver, which prefers IPv6, alternating between the families. Each attempt is given
+    /// 250 ms before the next address is tried in parallel, and a failed attempt hands over right away. The
+    /// first connection established wins and the others are abandoned. A and AAAA records are resolved together
+    /// by the system resolver, so there is no separate resolution delay.
+    pub async fn connect_host(&self, host: &str, port: u16) -> io::Result<SslStream<TcpStream>> {
+        let addrs = (host, port).to_socket_addrs_async().await?;
+        let stream = happy_eyeballs(interleave(addrs.collect())).await?;
+        Ok(self.connect(host, stream).await?)
+    }
+
+    /// Connects to `addr`, sends `request` and returns the whole response, closing the connection afterwards.
+    ///
+    /// A shortcut for one-shot clients like health checks. The peer has to close the connection after
+    /// responding, e.g. an HTTP/1.1 server answering a request with `Connection: close`. The peer certificate is
+    /// verified for `domain` just as by [`connect`](TlsConnector::connect).
+    pub async fn oneshot(
+        &self,
+        domain: &str,
+        addr: impl ToSocketAddrsAsync,
+        request: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = self.connect(domain, stream).await?;
+        stream.write_all(request.to_vec()).await.0?;
+        let BufResult(ret, response) = stream.read_to_end(Vec::new()).await;
+        ret?;
+        stream.shutdown().await?;
+        Ok(response)
+    }
+}
+
+impl From<SslConnector> for TlsConnector {
+    fn from(value: SslConnector) -> Self {
+        TlsConnector::new(value)
+    }
+}
+
+/// Orders `addrs` alternating between address families, starting with the family of the first one.
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let (preferred, other): (Vec<SocketAddr>, Vec<_>) = addrs.iter().partition(|a| a.is_ipv6() == first_v6);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut ordered = Vec::with_capacity(addrs.len());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connects to the first of `addrs` accepting a connection, starting attempts as described by RFC 8305.
+pub(crate) async fn happy_eyeballs(addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    type Attempt = Pin<Box<dyn Future<Output = io::Result<TcpStream>>>>;
+
+    let mut addrs = addrs.into_iter();
+    let mut attempts: Vec<Attempt> = Vec::new();
+    let mut delay: Option<Pin<Box<dyn Future<Output = ()>>>> = None;
+    let mut last_err = None;
+    poll_fn(|cx| {
+        loop {
+            let mut start_next = attempts.is_empty();
+            let mut i = 0;
+            while i < attempts.len() {
+                match attempts[i].as_mut().poll(cx) {
+                    Poll::Ready(Ok(stream)) => return Poll::Ready(Ok(stream)),
+                    Poll::Ready(Err(e)) => {
+                        drop(attempts.swap_remove(i));
+                        last_err = Some(e);
+                        start_next = true;
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            if !start_next && delay.as_mut().is_some_and(|d| d.as_mut().poll(cx).is_ready()) {
+                start_next = true;
+            }
+            if !start_next {
+                return Poll::Pending;
+            }
+            match addrs.next() {
+                Some(addr) => {
+                    attempts.push(Box::pin(TcpStream::connect(addr)));
+                    delay = Some(Box::pin(compio::time::sleep(CONNECTION_ATTEMPT_DELAY)));
+                }
+                None if attempts.is_empty() => {
+                    let err = last_err.take();
+                    return Poll::Ready(Err(err.unwrap_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")
+                    })));
+                }
+                None => {
+                    delay = None;
+                    return Poll::Pending;
+                }
+            }
+        }
+    })
+    .await
+}
+
+/// DNS names of the subject alternative name extension, or the common names if there are none.
+fn cert_names(cert: &X509Ref) -> Vec<String> {
+    if let Some(names) = cert.subject_alt_names() {
+        let names: Vec<_> = names.iter().filter_map(|n| n.dnsname()).map(str::to_owned).collect();
+        if !names.is_empty() {
+            return names;
+        }
+    }
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|e| e.data().to_string().ok())
+        .collect()
+}
+
+/// An error returned by [`TlsConnector::connect`].
+pub enum ConnectError<S> {
+    /// The peer certificate is trusted but not valid for the requested host name.
+    HostnameMismatch {
+        /// The host name we connected to.
+        expected: String,
+        /// The names the peer certificate is valid for.
+        cert_names: Vec<String>,
+    },
+    /// Any other I/O or TLS error.
+    Handshake(HandshakeError<S>),
+}
+
+impl<S> fmt::Debug for ConnectError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::HostnameMismatch { expected, cert_names } => f
+                .debug_struct("HostnameMismatch")
+                .field("expected", expected)
+                .field("cert_names", cert_names)
+                .finish(),
+            ConnectError::Handshake(e) => f.debug_tuple("Handshake").field(e).finish(),
+        }
+    }
+}
+
+impl<S> fmt::Display for ConnectError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::HostnameMismatch { expected, cert_names } => {
+                write!(
+                    f,
+                    "certificate is not valid for `{expected}`, but for {cert_names:?}"
+                )
+            }
+            // the handshake error is the source, so it is not repeated here
+            ConnectError::Handshake(_) => f.write_str("failed to connect over TLS"),
+        }
+    }
+}
+
+impl<S: 'static> Error for ConnectError<S> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConnectError::HostnameMismatch { .. } => None,
+            ConnectError::Handshake(e) => Some(e),
+        }
+    }
+}
+
+impl<S> From<io::Error> for ConnectError<S> {
+    fn from(value: io::Error) -> Self {
+        ConnectError::Handshake(value.into())
+    }
+}
+
+impl<S> From<ErrorStack> for ConnectError<S> {
+    fn from(value: ErrorStack) -> Self {
+        ConnectError::Handshake(value.into())
+    }
+}
+
+impl<S> From<ConnectError<S>> for io::Error {
+    fn from(value: ConnectError<S>) -> Self {
+        match value {
+            ConnectError::Handshake(e) => e.into(),
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
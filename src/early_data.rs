@@ -0,0 +1,66 @@
+use std::ffi::{c_int, c_void};
+use std::sync::OnceLock;
+
+use foreign_types::ForeignTypeRef;
+use openssl::ex_data::Index;
+use openssl::ssl::{SslContext, SslContextBuilder, SslRef};
+
+use crate::ffi;
+
+type ReplayCheck = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+fn check_index() -> Index<SslContext, ReplayCheck> {
+    static INDEX: OnceLock<Index<SslContext, ReplayCheck>> = OnceLock::new();
+    *INDEX.get_or_init(|| SslContext::new_ex_index().expect("failed to allocate ex data index"))
+}
+
+/// Lets `check` decide whether a server accepts the early data of a TLS 1.3 resumption, e.g. by looking it up
+/// in a replay cache shared by all servers.
+///
+/// Early data is sent before the server had a chance to contribute any randomness, so an attacker who
+/// recorded a ClientHello with its early data can send both again, and the server decrypts and processes the
+/// same request a second time. This is not limited to the original server: every server sharing the ticket
+/// key accepts the copy. A non-idempotent request, e.g. a payment, is then executed twice, and even
+/// idempotent ones leak through timing or side effects. OpenSSL's own protection only allows a single use of
+/// each ticket, within one process and only for sessions in its cache; deployments spanning several servers
+/// disable it with the `SSL_OP_NO_ANTI_REPLAY` option and need an external replay cache instead.
+///
+/// `check` is called with the 32 byte random of the ClientHello, before any early data is read. A copy of a
+/// ClientHello carries the same random, while the PSK binder keeps an attacker from changing it, so a random
+/// seen before identifies a replay. `check` should record the random and return `false` if it was already
+/// present, `true` otherwise. Entries only have to be kept as long as the tickets are valid, see
+/// [`set_session_timeout`](crate::set_session_timeout), and a cache unable to answer, e.g. when unreachable,
+/// should reject.
+///
+/// On rejection, the handshake continues as a regular resumption: OpenSSL skips the early data and the
+/// server reads nothing from [`read_realy_data`](crate::SslStream::read_realy_data). The client learns it
+/// from [`early_data_accepted`](crate::SslStream::early_data_accepted) and has to send the data again once
+/// the handshake completed, which [`connect_with_early_data`](crate::SslStream::connect_with_early_data)
+/// does. Only that copy is protected against replay. A replayed handshake never completes, as the attacker
+/// cannot produce the client's `Finished` message, so nothing sent after the handshake is ever processed twice.
+///
+/// The server still has to call [`read_realy_data`](crate::SslStream::read_realy_data) before completing the
+/// handshake for early data to be accepted at all.
+///
+/// Reference: [`SSL_CTX_set_allow_early_data_cb`](https://docs.openssl.org/master/man3/SSL_read_early_data/)
+pub fn set_early_data_replay_check<F>(builder: &mut SslContextBuilder, check: F)
+where
+    F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+{
+    builder.set_ex_data(check_index(), Box::new(check) as ReplayCheck);
+    // SAFETY: the context is valid, and the callback only reads the check stored in it
+    unsafe {
+        ffi::SSL_CTX_set_allow_early_data_cb(builder.as_ptr(), Some(allow_early_data), std::ptr::null_mut())
+    };
+}
+
+unsafe extern "C" fn allow_early_data(ssl: *mut ffi::SSL, _arg: *mut c_void) -> c_int {
+    // SAFETY: OpenSSL passes the valid `SSL` of the handshake
+    let ssl = unsafe { SslRef::from_ptr(ssl) };
+    let Some(check) = ssl.ssl_context().ex_data(check_index()) else {
+        return 0;
+    };
+    let mut random = [0; 32];
+    let len = ssl.client_random(&mut random);
+    check(&random[..len]) as c_int
+}
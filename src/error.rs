@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use openssl::error::ErrorStack;
+use openssl::ssl;
+
+use crate::{SslStream, ffi};
+
+/// An error caused by a record of the peer that was damaged on its way, e.g. by a middlebox.
+#[derive(Debug)]
+pub(crate) struct CorruptRecordError(pub(crate) ssl::Error);
+
+impl CorruptRecordError {
+    /// Whether OpenSSL rejected a record as malformed or failing authentication.
+    ///
+    /// Neither happens between two conforming TLS implementations, unlike failures to agree on parameters,
+    /// so they point to something altering the bytes in between.
+    pub(crate) fn matches(err: &ssl::Error) -> bool {
+        err.ssl_error().is_some_and(|stack| {
+            stack.errors().iter().any(|e| {
+                e.library_code() == ffi::ERR_LIB_SSL
+                    && matches!(
+                        e.reason_code(),
+                        ffi::SSL_R_DECRYPTION_FAILED
+                            | ffi::SSL_R_PACKET_LENGTH_TOO_LONG
+                            | ffi::SSL_R_RECORD_LENGTH_MISMATCH
+                            | ffi::SSL_R_WRONG_VERSION_NUMBER
+                            | ffi::SSL_R_DECRYPTION_FAILED_OR_BAD_RECORD_MAC
+                    )
+            })
+        })
+    }
+}
+
+impl fmt::Display for CorruptRecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TLS record corrupted in transit, possibly by a middlebox")
+    }
+}
+
+impl Error for CorruptRecordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        // `ssl::Error` would display its error stack and return it as its source as well
+        match self.0.ssl_error() {
+            Some(stack) => Some(stack),
+            None => Some(&self.0),
+        }
+    }
+}
+
+/// An error returned by handshakes consuming the transport, e.g. [`TlsAcceptor::accept`](crate::TlsAcceptor::accept).
+pub enum HandshakeError<S> {
+    /// The handshake failed.
+    ///
+    /// The stream is given back for diagnostics, e.g. [`SslRef::verify_result`](openssl::ssl::SslRef::verify_result)
+    /// or [`SslStream::last_alert`]. It cannot be used to transfer data.
+    Failure(SslStream<S>, io::Error),
+    /// Setting up the stream failed before the handshake started.
+    Io(io::Error),
+}
+
+impl<S> HandshakeError<S> {
+    /// Returns the stream of a failed handshake, if any.
+    pub fn stream(&self) -> Option<&SslStream<S>> {
+        match self {
+            HandshakeError::Failure(stream, _) => Some(stream),
+            HandshakeError::Io(_) => None,
+        }
+    }
+
+    /// Returns the underlying error.
+    pub fn error(&self) -> &io::Error {
+        match self {
+            HandshakeError::Failure(_, e) | HandshakeError::Io(e) => e,
+        }
+    }
+
+    /// Consumes the error, dropping the stream, returning the underlying error.
+    pub fn into_io_error(self) -> io::Error {
+        match self {
+            HandshakeError::Failure(_, e) | HandshakeError::Io(e) => e,
+        }
+    }
+}
+
+impl<S> fmt::Debug for HandshakeError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Failure(_, e) => f.debug_tuple("Failure").field(&"..").field(e).finish(),
+            HandshakeError::Io(e) => f.debug_tuple("Io").field(e).finish(),
+        }
+    }
+}
+
+impl<S> fmt::Display for HandshakeError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the underlying error is the source, so it is not repeated here
+        match self {
+            HandshakeError::Failure(..) => f.write_str("TLS handshake failed"),
+            HandshakeError::Io(_) => f.write_str("failed to set up the TLS stream"),
+        }
+    }
+}
+
+impl<S> Error for HandshakeError<S> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error())
+    }
+}
+
+impl<S> From<io::Error> for HandshakeError<S> {
+    fn from(value: io::Error) -> Self {
+        HandshakeError::Io(value)
+    }
+}
+
+impl<S> From<ErrorStack> for HandshakeError<S> {
+    fn from(value: ErrorStack) -> Self {
+        HandshakeError::Io(value.into())
+    }
+}
+
+impl<S> From<HandshakeError<S>> for io::Error {
+    fn from(value: HandshakeError<S>) -> Self {
+        value.into_io_error()
+    }
+}
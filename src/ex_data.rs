@@ -0,0 +1,39 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::sync::{Mutex, OnceLock};
+
+use openssl::ex_data::Index;
+use openssl::ssl::{Ssl, SslRef};
+
+/// The ex data index of `T`, allocated once per type for the whole process.
+fn index<T: Send + Sync + 'static>() -> Index<Ssl, T> {
+    static INDICES: OnceLock<Mutex<HashMap<TypeId, c_int>>> = OnceLock::new();
+    let mut indices = INDICES.get_or_init(Default::default).lock().unwrap();
+    let raw = *indices
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Ssl::new_ex_index::<T>().expect("failed to allocate ex data index").as_raw());
+    // SAFETY: the index was allocated for `T`, and is only ever used with `T`
+    unsafe { Index::from_raw(raw) }
+}
+
+/// Attaches `value` to `ssl`, replacing and dropping any previous value of the same type.
+///
+/// One value per type can be attached, so wrap plain types (e.g. a connection ID `u64`) in a dedicated type
+/// to avoid clashes with other users. The value lives as long as the [`Ssl`], and is dropped with it. As the
+/// `Ssl` may be moved to or shared with other threads, `T` must be `Send + Sync`.
+///
+/// Reference: [`SslRef::set_ex_data`]
+pub fn set_ex_data<T: Send + Sync + 'static>(ssl: &mut SslRef, value: T) {
+    ssl.set_ex_data(index::<T>(), value)
+}
+
+/// Returns the value of type `T` attached by [`set_ex_data`], if any.
+///
+/// This is meant to be called from OpenSSL callbacks, e.g. certificate verification or ALPN selection,
+/// which are given the [`SslRef`] but not the stream.
+///
+/// Reference: [`SslRef::ex_data`]
+pub fn ex_data<T: Send + Sync + 'static>(ssl: &SslRef) -> Option<&T> {
+    ssl.ex_data(index::<T>())
+}
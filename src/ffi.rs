@@ -0,0 +1,98 @@
+//! Bindings missing from `openssl-sys`.
+
+#![allow(non_upper_case_globals)]
+
+use std::ffi::{c_char, c_int, c_long, c_ulong, c_void};
+
+pub use openssl_sys::*;
+
+pub const ERR_LIB_SSL: c_int = 20;
+pub const SSL_AD_REASON_OFFSET: c_int = 1000;
+
+pub const SSL_R_DECRYPTION_FAILED: c_int = 147;
+pub const SSL_R_PACKET_LENGTH_TOO_LONG: c_int = 198;
+pub const SSL_R_RECORD_LENGTH_MISMATCH: c_int = 213;
+pub const SSL_R_WRONG_VERSION_NUMBER: c_int = 267;
+pub const SSL_R_DECRYPTION_FAILED_OR_BAD_RECORD_MAC: c_int = 281;
+
+pub const SSL_CTRL_SET_MAX_CERT_LIST: c_int = 51;
+pub const SSL_CTRL_CLEAR_MODE: c_int = 78;
+pub const SSL_CTRL_GET_PEER_SIGNATURE_NID: c_int = 108;
+#[cfg(all(ossl111, not(ossl300)))]
+pub const SSL_CTRL_GET_PEER_TMP_KEY: c_int = 109;
+#[cfg(ossl300)]
+pub const SSL_CTRL_GET_NEGOTIATED_GROUP: c_int = 134;
+#[cfg(ossl300)]
+pub const SSL_CTRL_SET_RETRY_VERIFY: c_int = 136;
+
+#[cfg(ossl300)]
+pub const SSL_ERROR_WANT_RETRY_VERIFY: c_int = 12;
+
+#[cfg(any(ossl111, libressl340))]
+pub const SSL_EARLY_DATA_ACCEPTED: c_int = 2;
+
+#[cfg(ossl111)]
+pub const TLSEXT_max_fragment_length_512: u8 = 1;
+#[cfg(ossl111)]
+pub const TLSEXT_max_fragment_length_4096: u8 = 4;
+
+#[cfg(ossl320)]
+pub const TLSEXT_comp_cert_zlib: c_int = 1;
+#[cfg(ossl320)]
+pub const TLSEXT_comp_cert_brotli: c_int = 2;
+#[cfg(ossl320)]
+pub const TLSEXT_comp_cert_zstd: c_int = 3;
+
+unsafe extern "C" {
+    pub fn BIO_number_read(bio: *mut BIO) -> u64;
+    pub fn BIO_number_written(bio: *mut BIO) -> u64;
+    pub fn SSL_CTX_set_timeout(ctx: *mut SSL_CTX, t: c_long) -> c_long;
+    pub fn SSL_set_read_ahead(s: *mut SSL, yes: c_int);
+    pub fn SSL_get_read_ahead(s: *const SSL) -> c_int;
+    #[cfg(ossl110)]
+    pub fn SSL_get_state(ssl: *const SSL) -> c_int;
+    #[cfg(ossl110)]
+    pub fn SSL_SESSION_get_ticket_lifetime_hint(s: *const SSL_SESSION) -> c_ulong;
+    #[cfg(any(ossl111, libressl340))]
+    pub fn SSL_get_early_data_status(s: *const SSL) -> c_int;
+    pub fn SSL_CTX_set_cert_cb(
+        ctx: *mut SSL_CTX,
+        cb: Option<unsafe extern "C" fn(*mut SSL, *mut c_void) -> c_int>,
+        arg: *mut c_void,
+    );
+    pub fn SSL_CTX_set_cert_verify_callback(
+        ctx: *mut SSL_CTX,
+        cb: Option<unsafe extern "C" fn(*mut X509_STORE_CTX, *mut c_void) -> c_int>,
+        arg: *mut c_void,
+    );
+    #[cfg(ossl111)]
+    pub fn SSL_CTX_set_allow_early_data_cb(
+        ctx: *mut SSL_CTX,
+        cb: Option<unsafe extern "C" fn(*mut SSL, *mut c_void) -> c_int>,
+        arg: *mut c_void,
+    );
+    #[cfg(ossl111)]
+    pub fn SSL_get_peer_signature_type_nid(s: *const SSL, pnid: *mut c_int) -> c_int;
+    #[cfg(ossl111)]
+    pub fn SSL_set_tlsext_max_fragment_length(ssl: *mut SSL, mode: u8) -> c_int;
+    #[cfg(ossl111)]
+    pub fn SSL_SESSION_has_ticket(s: *const SSL_SESSION) -> c_int;
+    #[cfg(ossl111)]
+    pub fn SSL_SESSION_get_max_fragment_length(session: *const SSL_SESSION) -> u8;
+    #[cfg(ossl300)]
+    pub fn SSL_set_options(s: *mut SSL, op: u64) -> u64;
+    #[cfg(ossl300)]
+    pub fn SSL_clear_options(s: *mut SSL, op: u64) -> u64;
+    #[cfg(ossl300)]
+    pub fn SSL_get_options(s: *const SSL) -> u64;
+    #[cfg(ossl300)]
+    pub fn SSL_new_session_ticket(s: *mut SSL) -> c_int;
+    #[cfg(ossl320)]
+    pub fn SSL_get_negotiated_client_cert_comp(s: *const SSL) -> c_int;
+    #[cfg(ossl320)]
+    pub fn SSL_get_negotiated_server_cert_comp(s: *const SSL) -> c_int;
+    #[cfg(ossl300)]
+    pub fn SSL_group_to_name(s: *mut SSL, id: c_int) -> *const c_char;
+    #[cfg(ossl300)]
+    pub fn OSSL_PROVIDER_available(libctx: *mut OSSL_LIB_CTX, name: *const c_char) -> c_int;
+}
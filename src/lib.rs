@@ -3,103 +3,1726 @@
 //! You can use [`SslStream::new`] to build a stream just like [`openssl:ssl::SslStream`](ssl::SslStream::new)
 //! or setup a stream manually and convert it to [`SslStream`] using [`SslStream::from`].
 
+use std::collections::VecDeque;
+use std::ffi::{CStr, c_int};
+use std::future::poll_fn;
 use std::io::{self, ErrorKind, Write};
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, RawFd};
+use std::pin::pin;
 use std::result::Result;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
 
 use compio::BufResult;
-use compio::buf::{IoBuf, IoBufMut};
+use compio::buf::{IntoInner, IoBuf, IoBufMut};
+use compio::fs::File;
 use compio::io::compat::SyncStream;
-use compio::io::{AsyncRead, AsyncWrite};
+use compio::io::{AsyncRead, AsyncReadAt, AsyncWrite, AsyncWriteExt};
+#[cfg(unix)]
+use compio::net::TcpStream;
+use foreign_types::ForeignTypeRef;
+use futures_channel::mpsc;
 use openssl::error::ErrorStack;
-use openssl::ssl::{self, ErrorCode, ShutdownResult, ShutdownState, Ssl, SslRef};
+use openssl::nid::Nid;
+#[cfg(ossl111)]
+use openssl::pkey::{PKey, Public};
+use openssl::ssl::{
+    self, AlpnError, ErrorCode, ShutdownResult, ShutdownState, Ssl, SslCipherRef, SslContextBuilder,
+    SslContextRef, SslMode, SslOptions, SslRef, SslSessionCacheMode, SslVersion,
+};
+use openssl::x509::X509VerifyResult;
+use openssl::x509::verify::X509CheckFlags;
 
+mod acceptor;
+mod acme;
+mod alert;
+#[cfg(feature = "sync-compat")]
+mod blocking;
+#[cfg(ossl111)]
+mod cert_callback;
+#[cfg(ossl111)]
+mod client_hello;
+mod connector;
+#[cfg(ossl111)]
+mod early_data;
+mod error;
+mod ex_data;
+mod ffi;
+mod lines;
+mod positioned;
+mod rate_limit;
+mod self_signed;
+mod split;
+#[cfg(feature = "tap")]
+mod tap;
 #[cfg(test)]
 mod test;
+#[cfg(feature = "test-util")]
+mod test_util;
+mod transport;
+#[cfg(ossl300)]
+mod verify;
+
+pub use acceptor::{TlsAcceptor, acceptor_from_pem};
+pub use acme::{ACME_TLS_ALPN, acme_tls_alpn_cert, set_acme_alpn_select};
+pub use alert::Alert;
+use alert::PeerAlertError;
+#[cfg(feature = "sync-compat")]
+pub use blocking::BlockingStream;
+#[cfg(ossl111)]
+pub use cert_callback::{CertResponse, pause_cert_lookup, set_cert_callback};
+#[cfg(ossl111)]
+pub use client_hello::{
+    ClientHelloInfo, client_hello_ciphers, client_hello_extensions, client_hello_versions,
+    enable_client_hello_peek, pause_handshake,
+};
+pub use connector::{ConnectError, TlsConnector, connector_from_ca_pem};
+#[cfg(ossl111)]
+pub use early_data::set_early_data_replay_check;
+use error::CorruptRecordError;
+pub use error::HandshakeError;
+pub use ex_data::{ex_data, set_ex_data};
+pub use lines::Lines;
+pub use positioned::PositionedStream;
+use rate_limit::RateLimit;
+pub use split::{ReadHalf, ReuniteError, WriteHalf};
+#[cfg(feature = "tap")]
+pub use tap::{Tap, TapDirection};
+#[cfg(feature = "test-util")]
+pub use test_util::generate_self_signed;
+pub use transport::{AsyncTransport, BoxedSslStream};
+#[cfg(ossl300)]
+pub use verify::{pause_verification, set_cert_verify_callback};
 
 /// Compio asynchronous version of [`openssl:ssl::SslStream`](ssl::SslStream).
+///
+/// Like every compio stream, `&mut SslStream` implements [`AsyncRead`] and [`AsyncWrite`] as well, through
+/// compio's blanket implementations, so it can be lent to generic code without moving it.
+///
+/// Reads decrypt straight into the caller's buffer and writes encrypt straight from it, so any owned buffer
+/// works, e.g. a page-aligned one implementing [`IoBufMut`] for direct I/O elsewhere, without extra copies
+/// or alignment requirements. The buffer is never submitted to the kernel itself: the transport is driven
+/// through an internal buffer, and kernel TLS is not used, as OpenSSL only enables it on socket BIOs.
+///
+/// # Cancel safety
+///
+/// No async method waiting for the transport is cancel safe, including [`read`](AsyncRead::read),
+/// [`write`](AsyncWrite::write), [`flush`](AsyncWrite::flush), [`shutdown`](AsyncWrite::shutdown),
+/// [`accept`](Self::accept), [`connect`](Self::connect) and [`peek`](Self::peek). compio is completion-based:
+/// a pending transport read or write owns the internal buffer until the kernel is done with it, so dropping
+/// its future, e.g. when a timer wins a `select!`, loses the buffer along with the bytes in flight. The
+/// session cannot continue without them, and every later operation fails with an error asking to drop the
+/// stream. A future dropped before it waited for the transport, e.g. one never polled, leaves the stream
+/// intact.
+///
+/// To bound the time an operation takes, use [`accept_with_cancel`](Self::accept_with_cancel),
+/// [`read_to_end_timeout`](Self::read_to_end_timeout) or [`shutdown_timeout`](Self::shutdown_timeout), or
+/// race the operation against a timer and drop the stream when the timer wins. A connection reading and
+/// writing concurrently is [`split`](Self::split) rather than raced in a loop.
 #[derive(Debug)]
 pub struct SslStream<S> {
     stream: ssl::SslStream<SyncStream<S>>,
+    write_buf: Vec<u8>,
+    write_threshold: usize,
+    flush_on_write: bool,
+    flushed: u64,
+    last_alert: Option<Alert>,
+    bytes_read: u64,
+    bytes_written: u64,
+    last_want: Option<HandshakeWant>,
+    max_io_rounds: Option<usize>,
+    close_notify_eof_error: bool,
+    transport_busy: bool,
+    tee: Option<mpsc::Sender<Vec<u8>>>,
+    read_limit: Option<RateLimit>,
+    write_limit: Option<RateLimit>,
+    read_ring_error: Option<io::Error>,
+}
+
+/// The largest record plaintext TLS allows, and OpenSSL's default `max_send_fragment`.
+const MAX_RECORD_PLAINTEXT: usize = 16 * 1024;
+
+/// The number of reads a [`SslStream::tee`] observer may lag behind.
+const TEE_CHUNKS: usize = 64;
+
+/// How far a [`SslStream::shutdown_timeout`] got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// Both sides sent `close_notify`.
+    Clean,
+    /// Our `close_notify` was sent, but the peer's did not arrive in time.
+    SentOnly,
+}
+
+/// How the handshake of a connection established its keys, see [`SslStream::resumption_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumptionKind {
+    /// A full handshake, authenticated with certificates.
+    Full,
+    /// A TLS 1.2 session resumed by its ID, from the server's session cache.
+    SessionId,
+    /// A session resumed from a session ticket, TLS 1.3 resumptions included.
+    Ticket,
+    /// A handshake authenticated with an external pre-shared key.
+    Psk,
+}
+
+/// Verification results recorded by [`SslStream::record_verify_errors`].
+#[derive(Clone, Default)]
+struct VerifyResults(Arc<Mutex<Vec<(u32, X509VerifyResult)>>>);
+
+/// The transport readiness a handshake waits for, see [`SslStream::handshake_wants`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeWant {
+    /// The handshake waits for data from the peer.
+    WantRead,
+    /// The handshake waits for its output to be written to the transport.
+    WantWrite,
+}
+
+/// The outcome of a single [`SslStream::try_handshake`] step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeStep {
+    /// The handshake is complete, and its output written to the transport.
+    Done,
+    /// Call [`SslStream::fill_read_buf`], then step again.
+    WantRead,
+    /// Call [`SslStream::flush_write_buf`], then step again.
+    WantWrite,
 }
 
 impl<S: AsyncRead + AsyncWrite> SslStream<S> {
     /// Create a new `SslStream`.
     ///
-    /// Reference: [`SslStream::new`](ssl::SslStream::new)
-    pub fn new(ssl: Ssl, stream: S) -> Result<SslStream<S>, ErrorStack> {
-        let stream = ssl::SslStream::new(ssl, SyncStream::new(stream))?;
-        Ok(SslStream { stream })
+    /// Reference: [`SslStream::new`](ssl::SslStream::new)
+    pub fn new(ssl: Ssl, stream: S) -> Result<SslStream<S>, ErrorStack> {
+        let stream = ssl::SslStream::new(ssl, SyncStream::new(stream))?;
+        Ok(SslStream::from(stream))
+    }
+
+    /// Create a new `SslStream` with a fresh [`Ssl`] of the context.
+    ///
+    /// Use [`SslStream::new`] if the `Ssl` needs to be configured beforehand.
+    pub fn from_context(ctx: &SslContextRef, stream: S) -> Result<SslStream<S>, ErrorStack> {
+        SslStream::new(Ssl::new(ctx)?, stream)
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    ///
+    /// # Warning
+    ///
+    /// Any read/write operation to the stream would most likely corrupt the SSL session.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut().get_mut()
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    #[inline(always)]
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref().get_ref()
+    }
+
+    /// Returns a shared reference to the [`Ssl`] object associated with this stream.
+    #[inline(always)]
+    pub fn ssl(&self) -> &SslRef {
+        self.stream.ssl()
+    }
+
+    /// Returns a mutable reference to the [`Ssl`] object associated with this stream.
+    ///
+    /// # Warning
+    ///
+    /// Changing the configuration of an established session (e.g. verification settings or ciphers after the
+    /// handshake) would most likely corrupt the SSL session. Prefer doing so before [`accept`](Self::accept)
+    /// or [`connect`](Self::connect).
+    #[inline(always)]
+    pub fn ssl_mut(&mut self) -> &mut SslRef {
+        // SAFETY: the `Ssl` is uniquely owned by `self.stream`, which we borrow mutably
+        unsafe { SslRef::from_ptr_mut(self.stream.ssl().as_ptr()) }
+    }
+
+    /// Consumes the `SslStream`, returning the underlying [`openssl:ssl::SslStream`](ssl::SslStream).
+    ///
+    /// The caller then owns driving the asynchronous I/O manually: on `WANT_READ`/`WANT_WRITE`, call
+    /// [`SyncStream::fill_read_buf`] or [`SyncStream::flush_write_buf`] on the inner stream before retrying.
+    #[inline(always)]
+    pub fn into_openssl(self) -> ssl::SslStream<SyncStream<S>> {
+        self.stream
+    }
+
+    /// Returns the protocol selected via Application Layer Protocol Negotiation (ALPN).
+    ///
+    /// Reference: [`SslRef::selected_alpn_protocol`]
+    #[inline(always)]
+    pub fn selected_alpn_protocol(&self) -> Option<&[u8]> {
+        self.stream.ssl().selected_alpn_protocol()
+    }
+
+    /// Like [`selected_alpn_protocol`](Self::selected_alpn_protocol), but as a string.
+    ///
+    /// Returns `None` as well if the protocol is not valid UTF-8.
+    #[inline(always)]
+    pub fn selected_alpn_protocol_str(&self) -> Option<&str> {
+        self.selected_alpn_protocol().and_then(|p| std::str::from_utf8(p).ok())
+    }
+
+    /// Returns the protocol selected via ALPN, or `default` if none was, e.g. `b"http/1.1"` for a peer that
+    /// does not support ALPN.
+    #[inline(always)]
+    pub fn alpn_or<'a>(&'a self, default: &'a [u8]) -> &'a [u8] {
+        self.selected_alpn_protocol().unwrap_or(default)
+    }
+
+    /// Returns the identity of the external pre-shared key the connection was established with, if any.
+    ///
+    /// OpenSSL only reports it for TLS 1.2. With TLS 1.3, it is only known if the PSK was configured with
+    /// [`set_psk_client`] or [`set_psk_server`]. Session resumption is not an external PSK, and has no identity
+    /// here.
+    ///
+    /// Reference: [`SSL_get_psk_identity`](https://docs.openssl.org/master/man3/SSL_get_psk_identity/)
+    pub fn psk_identity(&self) -> Option<&[u8]> {
+        let ssl = self.ssl();
+        ssl.psk_identity().or_else(|| ex_data::ex_data::<PskIdentity>(ssl).map(|identity| &identity.0[..]))
+    }
+
+    /// Buffers plaintext of small writes until at least `n` bytes are pending, then encrypts them together.
+    ///
+    /// `SslStream` already buffers the encrypted bytes, but every write is still encrypted into its own TLS
+    /// record. Many small writes thus cost a record header and MAC each. With a threshold, they are aggregated
+    /// into fewer, larger records instead. Pending plaintext is sent on [`flush`](AsyncWrite::flush) and
+    /// [`shutdown`](AsyncWrite::shutdown).
+    ///
+    /// Prefer this over wrapping the stream in compio's `BufWriter`, which would copy the plaintext once
+    /// more. `0`, the default, disables the buffering.
+    #[inline(always)]
+    pub fn set_write_buffer_threshold(&mut self, n: usize) {
+        self.write_threshold = n;
+    }
+
+    /// Controls whether every write is sent to the transport as soon as it is encrypted, which is the default.
+    ///
+    /// When disabled, encrypted records are kept in the transport buffer until it is full, or until
+    /// [`flush`](AsyncWrite::flush) or [`shutdown`](AsyncWrite::shutdown). Reads and handshakes still flush
+    /// first, so a request is never held back while waiting for its response. See
+    /// [`pending_write_bytes`](Self::pending_write_bytes) for the amount buffered.
+    #[inline(always)]
+    pub fn set_flush_on_write(&mut self, enable: bool) {
+        self.flush_on_write = enable;
+    }
+
+    /// Limits how many transport reads a single operation, e.g. a handshake or a read, may wait for without
+    /// making progress. `None` removes the limit, which is the default.
+    ///
+    /// A peer sending its data in tiny pieces, down to a byte per packet, or in empty records, makes OpenSSL
+    /// retry the operation for each of them. Past the limit, the operation fails with [`ErrorKind::InvalidData`],
+    /// bounding the CPU time a single peer can consume. Rounds that write to the transport, or complete a
+    /// handshake message (from OpenSSL 1.1.0), are progress and start the count over, so a long handshake or a
+    /// slow but steady peer is not cut off. A record arrives in one or a few transport reads, even over small
+    /// TCP segments, so a limit of a few hundred leaves legitimate peers plenty of room.
+    #[inline(always)]
+    pub fn set_max_io_rounds(&mut self, max: Option<usize>) {
+        self.max_io_rounds = max;
+    }
+
+    /// Limits the decrypted bytes read to `bytes_per_sec`, e.g. to keep a single connection from monopolizing
+    /// the bandwidth. `None` removes the limit, which is the default.
+    ///
+    /// Reads wait on a timer once the connection used up its share. The limit is a token bucket allowing bursts
+    /// of up to one second's worth of bytes, and coarse-grained: it is checked before each read, so a read
+    /// taking more than its share delays the following ones instead. The peer is only slowed down once the
+    /// transport's buffers fill up. Setting a limit starts with a full bucket, and a rate of 0 counts as 1.
+    pub fn set_read_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.read_limit = bytes_per_sec.map(RateLimit::new);
+    }
+
+    /// Limits the plaintext bytes written to `bytes_per_sec`. `None` removes the limit, which is the default.
+    ///
+    /// The counterpart of [`set_read_rate_limit`](Self::set_read_rate_limit), checked before each write, so a
+    /// single large write still goes out at once, and delays the following ones.
+    pub fn set_write_rate_limit(&mut self, bytes_per_sec: Option<u64>) {
+        self.write_limit = bytes_per_sec.map(RateLimit::new);
+    }
+
+    /// Controls whether a `close_notify` from the peer fails reads with [`ErrorKind::UnexpectedEof`] instead of
+    /// returning `Ok(0)`, which is the default.
+    ///
+    /// For protocols that know the length of the content, e.g. HTTP with `Content-Length`, a clean close before
+    /// all of it arrived is still a truncation. Enable this while content is expected, and disable it again to
+    /// accept the close afterwards. A transport EOF without `close_notify` is unaffected and keeps returning
+    /// `Ok(0)`, this only covers the clean close, which the peer sent deliberately.
+    #[inline(always)]
+    pub fn treat_close_notify_as_eof_error(&mut self, enable: bool) {
+        self.close_notify_eof_error = enable;
+    }
+
+    /// Returns the number of encrypted bytes in the transport buffer, waiting to be flushed.
+    ///
+    /// Plaintext held back by [`set_write_buffer_threshold`](Self::set_write_buffer_threshold) is not
+    /// encrypted yet, and thus not counted. Neither are bytes left in the transport buffer of a stream given to
+    /// [`SslStream::from`], until the next flush.
+    pub fn pending_write_bytes(&self) -> usize {
+        (self.raw_bytes_written() - self.flushed) as usize
+    }
+
+    /// Lets OpenSSL free its record buffers while the connection is idle.
+    ///
+    /// With many idle keep-alive connections, these buffers (about 34 KiB each) dominate the memory usage.
+    /// The tradeoff is a reallocation whenever the connection becomes active again. The transport buffers of
+    /// `SslStream` itself are kept.
+    ///
+    /// Reference: [`SSL_MODE_RELEASE_BUFFERS`](https://docs.openssl.org/master/man3/SSL_CTX_set_mode/)
+    pub fn set_release_buffers(&mut self, enable: bool) {
+        let cmd = if enable {
+            ffi::SSL_CTRL_MODE
+        } else {
+            ffi::SSL_CTRL_CLEAR_MODE
+        };
+        // SAFETY: `ssl` is valid and these controls take no pointer argument
+        unsafe {
+            ffi::SSL_ctrl(
+                self.ssl().as_ptr(),
+                cmd,
+                SslMode::RELEASE_BUFFERS.bits() as _,
+                std::ptr::null_mut(),
+            );
+        }
+    }
+
+    /// Sets the list of supported ciphers for protocols before TLSv1.3.
+    ///
+    /// Must be called before [`accept`](Self::accept) or [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SslRef::set_cipher_list`]
+    #[inline(always)]
+    pub fn set_cipher_list(&mut self, list: &str) -> Result<(), ErrorStack> {
+        self.ssl_mut().set_cipher_list(list)
+    }
+
+    /// Sets the list of supported ciphers for the TLSv1.3 protocol.
+    ///
+    /// Must be called before [`accept`](Self::accept) or [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SslRef::set_ciphersuites`]
+    #[inline(always)]
+    #[cfg(any(ossl111, libressl340))]
+    pub fn set_ciphersuites(&mut self, list: &str) -> Result<(), ErrorStack> {
+        self.ssl_mut().set_ciphersuites(list)
+    }
+
+    /// Sets the minimum supported protocol version, `None` meaning the lowest version supported by OpenSSL.
+    ///
+    /// Must be called before [`accept`](Self::accept) or [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SslRef::set_min_proto_version`]
+    #[inline(always)]
+    pub fn set_min_proto_version(&mut self, version: Option<SslVersion>) -> Result<(), ErrorStack> {
+        self.ssl_mut().set_min_proto_version(version)
+    }
+
+    /// Sets the maximum supported protocol version, `None` meaning the highest version supported by OpenSSL.
+    ///
+    /// Must be called before [`accept`](Self::accept) or [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SslRef::set_max_proto_version`]
+    #[inline(always)]
+    pub fn set_max_proto_version(&mut self, version: Option<SslVersion>) -> Result<(), ErrorStack> {
+        self.ssl_mut().set_max_proto_version(version)
+    }
+
+    /// Sets the maximum number of intermediate certificates allowed in the peer's chain.
+    ///
+    /// Verification fails if the chain is longer. Must be called before [`accept`](Self::accept) or
+    /// [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SSL_set_verify_depth`](https://docs.openssl.org/master/man3/SSL_CTX_set_verify/)
+    #[inline(always)]
+    pub fn set_verify_depth(&mut self, depth: u32) {
+        self.ssl_mut().param_mut().set_depth(depth.try_into().unwrap_or(i32::MAX))
+    }
+
+    /// Sets how the host name expected by verification is matched against the peer's certificate.
+    ///
+    /// The host name itself is set with [`set_host`](openssl::x509::verify::X509VerifyParamRef::set_host) through `ssl_mut().param_mut()`, which
+    /// [`TlsConnector`] does. By default, a wildcard certificate for `*.example.com` matches `www.example.com`;
+    /// [`X509CheckFlags::NO_WILDCARDS`] makes strict clients reject it, so that only a certificate naming the
+    /// host exactly is accepted. Must be called before [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`X509_VERIFY_PARAM_set_hostflags`](https://docs.openssl.org/master/man3/X509_VERIFY_PARAM_set_flags/)
+    #[inline(always)]
+    pub fn set_hostname_flags(&mut self, flags: X509CheckFlags) {
+        self.ssl_mut().param_mut().set_hostflags(flags)
+    }
+
+    /// Installs a verify callback recording the result of every certificate checked, see
+    /// [`verify_errors`](Self::verify_errors).
+    ///
+    /// Must be called before [`accept`](Self::accept) or [`connect`](Self::connect). The verify mode is kept,
+    /// but a verify callback set before is replaced, and one set afterwards disables the recording.
+    pub fn record_verify_errors(&mut self) {
+        let results = VerifyResults::default();
+        let recorded = results.clone();
+        let mode = self.ssl().verify_mode();
+        self.ssl_mut().set_verify_callback(mode, move |preverify, ctx| {
+            recorded.0.lock().unwrap().push((ctx.error_depth(), ctx.error()));
+            preverify
+        });
+        ex_data::set_ex_data(self.ssl_mut(), results);
+    }
+
+    /// Returns the depth and verification result of every certificate checked during the handshake, in the
+    /// order OpenSSL checked them.
+    ///
+    /// Depth 0 is the peer's own certificate. Verification stops at the first failure, which is thus the last
+    /// entry, and the certificates not checked by then are missing. Empty unless
+    /// [`record_verify_errors`](Self::record_verify_errors) was called before the handshake.
+    pub fn verify_errors(&self) -> Vec<(u32, X509VerifyResult)> {
+        ex_data::ex_data::<VerifyResults>(self.ssl()).map_or_else(Vec::new, |r| r.0.lock().unwrap().clone())
+    }
+
+    /// Returns the length of the certificate chain the peer was verified with, from its own certificate up to
+    /// and including the trusted root.
+    ///
+    /// The peer sent `len - 2` intermediates that were actually needed, assuming it did not send the root. A
+    /// long chain makes every full handshake slower, so this helps spotting misconfigured peers. Returns `None`
+    /// unless the peer's certificate was verified successfully, and always before OpenSSL 1.1.0.
+    ///
+    /// Reference: [`SSL_get0_verified_chain`](https://docs.openssl.org/master/man3/SSL_get_peer_cert_chain/)
+    pub fn verified_chain_len(&self) -> Option<usize> {
+        #[cfg(ossl110)]
+        if self.ssl().verify_result() == X509VerifyResult::OK {
+            return self.ssl().verified_chain().map(|chain| chain.len());
+        }
+        None
+    }
+
+    /// Disables session tickets for this connection, so that it cannot be resumed through one.
+    ///
+    /// A server neither issues tickets nor accepts them; a client does not ask for them. In TLS 1.3, a server
+    /// does not even issue stateful tickets. With TLS 1.2, a server may still resume sessions by ID from its
+    /// session cache, which can be turned off on the context with [`SslSessionCacheMode::OFF`].
+    ///
+    /// Must be called before [`accept`](Self::accept) or [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SSL_OP_NO_TICKET`](https://docs.openssl.org/master/man3/SSL_CTX_set_options/)
+    pub fn disable_tickets(&mut self) -> Result<(), ErrorStack> {
+        self.set_options(SslOptions::NO_TICKET);
+        #[cfg(ossl111)]
+        self.set_num_tickets(0)?;
+        Ok(())
+    }
+
+    /// Sets the number of TLS 1.3 session tickets a server issues after the handshake of this connection.
+    ///
+    /// 0 disables TLS 1.3 tickets for this connection only, leaving TLS 1.2 tickets and other connections of
+    /// the context alone, unlike [`disable_tickets`](Self::disable_tickets). More than one lets a client resume
+    /// several connections in parallel, as each ticket should be used once. OpenSSL's default is 2.
+    ///
+    /// Must be called before [`accept`](Self::accept) to take effect. It has no effect on a client.
+    ///
+    /// Reference: [`SSL_set_num_tickets`](https://docs.openssl.org/master/man3/SSL_CTX_set_num_tickets/)
+    #[cfg(ossl111)]
+    pub fn set_num_tickets(&mut self, n: usize) -> Result<(), ErrorStack> {
+        self.ssl_mut().set_num_tickets(n)
+    }
+
+    /// Enables `opts` for this connection in addition to the options already set, returning the resulting set.
+    ///
+    /// A connection starts with the options of its context. Most options, e.g. [`SslOptions::NO_TICKET`] or
+    /// [`SslOptions::NO_RENEGOTIATION`], are only evaluated during the handshake, and must be set before
+    /// [`accept`](Self::accept) or [`connect`](Self::connect) to take effect.
+    ///
+    /// Reference: [`SSL_set_options`](https://docs.openssl.org/master/man3/SSL_CTX_set_options/)
+    pub fn set_options(&mut self, opts: SslOptions) -> SslOptions {
+        let ssl = self.ssl().as_ptr();
+        // SAFETY: `ssl` is valid, and the options control takes no pointer argument
+        #[cfg(ossl300)]
+        let bits = unsafe { ffi::SSL_set_options(ssl, opts.bits()) };
+        #[cfg(not(ossl300))]
+        let bits =
+            unsafe { ffi::SSL_ctrl(ssl, ffi::SSL_CTRL_OPTIONS, opts.bits() as _, std::ptr::null_mut()) };
+        SslOptions::from_bits_retain(bits as _)
+    }
+
+    /// Disables `opts` for this connection, returning the resulting set.
+    ///
+    /// See [`set_options`](Self::set_options) for when options take effect.
+    ///
+    /// Reference: [`SSL_clear_options`](https://docs.openssl.org/master/man3/SSL_CTX_set_options/)
+    pub fn clear_options(&mut self, opts: SslOptions) -> SslOptions {
+        let ssl = self.ssl().as_ptr();
+        // SAFETY: `ssl` is valid, and the options control takes no pointer argument
+        #[cfg(ossl300)]
+        let bits = unsafe { ffi::SSL_clear_options(ssl, opts.bits()) };
+        #[cfg(not(ossl300))]
+        let bits = unsafe {
+            ffi::SSL_ctrl(
+                ssl,
+                ffi::SSL_CTRL_CLEAR_OPTIONS,
+                opts.bits() as _,
+                std::ptr::null_mut(),
+            )
+        };
+        SslOptions::from_bits_retain(bits as _)
+    }
+
+    /// Controls TLS 1.3 middlebox compatibility mode for this connection, which OpenSSL enables by default.
+    ///
+    /// TLS 1.3 handshakes then look like resumed TLS 1.2 ones, with a session ID and dummy `ChangeCipherSpec`
+    /// records, as some firewalls and proxies drop or damage connections they do not recognize. Between peers
+    /// known to be reached directly, e.g. constrained devices on a private network, disabling it saves a few
+    /// bytes per handshake. Must be called before [`accept`](Self::accept) or [`connect`](Self::connect).
+    ///
+    /// Interference shows up as a record OpenSSL cannot parse or authenticate, failing the handshake or a read
+    /// with [`ErrorKind::InvalidData`] and a message naming the record corruption, rather than with the generic
+    /// error of a protocol failure. The peer seeing the same reports a `bad_record_mac` [`Alert`], see
+    /// [`last_alert`](Self::last_alert).
+    ///
+    /// Reference: [`SSL_OP_ENABLE_MIDDLEBOX_COMPAT`](https://docs.openssl.org/master/man3/SSL_CTX_set_options/)
+    #[cfg(ossl111)]
+    pub fn set_middlebox_compat(&mut self, enable: bool) {
+        if enable {
+            self.set_options(SslOptions::ENABLE_MIDDLEBOX_COMPAT);
+        } else {
+            self.clear_options(SslOptions::ENABLE_MIDDLEBOX_COMPAT);
+        }
+    }
+
+    /// Returns the options of this connection.
+    ///
+    /// Reference: [`SSL_get_options`](https://docs.openssl.org/master/man3/SSL_CTX_set_options/)
+    pub fn options(&self) -> SslOptions {
+        let ssl = self.ssl().as_ptr();
+        // SAFETY: `ssl` is valid, and the options control takes no pointer argument
+        #[cfg(ossl300)]
+        let bits = unsafe { ffi::SSL_get_options(ssl) };
+        #[cfg(not(ossl300))]
+        let bits = unsafe { ffi::SSL_ctrl(ssl, ffi::SSL_CTRL_OPTIONS, 0, std::ptr::null_mut()) };
+        SslOptions::from_bits_retain(bits as _)
+    }
+
+    /// Controls whether OpenSSL reads more than the record it is about to process from the transport.
+    ///
+    /// OpenSSL then asks for as much as fits its read buffer in one go, instead of the record header first
+    /// and its body next. As this stream reads the transport into a buffer of its own, taking whatever is
+    /// available, this does not save transport reads, only OpenSSL's calls into that buffer. The data read
+    /// ahead is held by OpenSSL: [`SslRef::pending`] only counts the decrypted bytes of the current record,
+    /// so a `pending` of 0 no longer means that nothing can be read without the transport, use
+    /// [`readable`](Self::readable) instead. Not to be used with DTLS, where each read must return a single
+    /// datagram.
+    ///
+    /// Reference: [`SSL_set_read_ahead`](https://docs.openssl.org/master/man3/SSL_CTX_set_read_ahead/)
+    pub fn set_read_ahead(&mut self, enable: bool) {
+        // SAFETY: the `Ssl` is valid
+        unsafe { ffi::SSL_set_read_ahead(self.ssl_mut().as_ptr(), enable as c_int) };
+    }
+
+    /// Returns whether read-ahead is enabled, see [`set_read_ahead`](Self::set_read_ahead).
+    pub fn read_ahead(&self) -> bool {
+        // SAFETY: the `Ssl` is valid
+        unsafe { ffi::SSL_get_read_ahead(self.ssl().as_ptr()) != 0 }
+    }
+
+    /// Returns the protocol version number of the connection, e.g. `0x0304` for TLS 1.3.
+    ///
+    /// Only meaningful once the handshake completed. Unlike [`SslRef::version_str`], it can be compared
+    /// programmatically.
+    ///
+    /// Reference: [`SSL_version`](https://docs.openssl.org/master/man3/SSL_get_version/)
+    pub fn protocol_version_number(&self) -> i32 {
+        // SAFETY: the `Ssl` is valid
+        unsafe { ffi::SSL_version(self.ssl().as_ptr()) }
+    }
+
+    /// Returns whether TLS 1.3 is negotiated.
+    #[inline(always)]
+    pub fn is_tlsv13(&self) -> bool {
+        self.protocol_version_number() == 0x0304
+    }
+
+    /// Returns the current cipher if the session is active.
+    ///
+    /// Reference: [`SslRef::current_cipher`]
+    #[inline(always)]
+    pub fn current_cipher(&self) -> Option<&SslCipherRef> {
+        self.stream.ssl().current_cipher()
+    }
+
+    /// Returns the signature type the peer used to sign the handshake, e.g. `RSA-PSS`.
+    ///
+    /// Reference: [`SSL_get_peer_signature_type_nid`](https://docs.openssl.org/master/man3/SSL_get_peer_signature_nid/)
+    #[cfg(ossl111)]
+    pub fn peer_signature_type(&self) -> Option<Nid> {
+        let mut nid = 0;
+        // SAFETY: `nid` is a valid out pointer
+        let ret = unsafe { ffi::SSL_get_peer_signature_type_nid(self.ssl().as_ptr(), &mut nid) };
+        (ret == 1).then(|| Nid::from_raw(nid))
+    }
+
+    /// Returns the digest the peer signed the handshake with, e.g. `SHA256`.
+    ///
+    /// The signature algorithm itself is given by [`peer_signature_type`](Self::peer_signature_type). Returns
+    /// `None` before the handshake, if the peer did not sign it, e.g. a TLS 1.2 RSA key exchange, and for
+    /// schemes without a separate digest, e.g. Ed25519.
+    ///
+    /// Reference: [`SSL_get_peer_signature_nid`](https://docs.openssl.org/master/man3/SSL_get_peer_signature_nid/)
+    pub fn peer_signature_nid(&self) -> Option<Nid> {
+        let mut nid: c_int = 0;
+        // SAFETY: `nid` is a valid out pointer
+        let ret = unsafe {
+            ffi::SSL_ctrl(
+                self.ssl().as_ptr(),
+                ffi::SSL_CTRL_GET_PEER_SIGNATURE_NID,
+                0,
+                &mut nid as *mut c_int as *mut _,
+            )
+        };
+        (ret == 1 && nid != ffi::NID_undef).then(|| Nid::from_raw(nid))
+    }
+
+    /// Returns the ephemeral public key the peer contributed to the key exchange, e.g. an X25519 or P-256 key.
+    ///
+    /// Returns `None` before the handshake, and for a key exchange without ephemeral key, i.e. the TLS 1.2 RSA
+    /// key transport, which offers no forward secrecy. Its [`id`](openssl::pkey::PKeyRef::id) tells ECDHE from finite field DHE.
+    ///
+    /// Reference: [`SSL_get_peer_tmp_key`](https://docs.openssl.org/master/man3/SSL_get_peer_tmp_key/)
+    #[cfg(ossl111)]
+    pub fn peer_tmp_key(&self) -> Option<PKey<Public>> {
+        use foreign_types::ForeignType;
+
+        let mut key = std::ptr::null_mut::<ffi::EVP_PKEY>();
+        // SAFETY: `key` is a valid out pointer, receiving a new reference on success
+        let ret = unsafe {
+            ffi::SSL_ctrl(
+                self.ssl().as_ptr(),
+                ffi::SSL_CTRL_GET_PEER_TMP_KEY,
+                0,
+                &mut key as *mut _ as *mut _,
+            )
+        };
+        if ret == 0 || key.is_null() {
+            return None;
+        }
+        // SAFETY: we own the reference returned above
+        Some(unsafe { PKey::from_ptr(key) })
+    }
+
+    /// Returns the name of the group used for the key exchange, e.g. `x25519`.
+    ///
+    /// Reference: [`SSL_get_negotiated_group`](https://docs.openssl.org/master/man3/SSL_CTX_set1_curves/)
+    #[cfg(ossl300)]
+    pub fn negotiated_group(&self) -> Option<&str> {
+        // OpenSSL reads the group from the session, crashing without one
+        self.ssl().session()?;
+        let ssl = self.ssl().as_ptr();
+        // SAFETY: `ssl` is valid and this control takes no pointer argument
+        let id = unsafe { ffi::SSL_ctrl(ssl, ffi::SSL_CTRL_GET_NEGOTIATED_GROUP, 0, std::ptr::null_mut()) };
+        if id == 0 {
+            return None;
+        }
+        // SAFETY: the returned name is either null or a static NUL-terminated string
+        let name = unsafe { ffi::SSL_group_to_name(ssl, id as _) };
+        if name.is_null() {
+            return None;
+        }
+        unsafe { CStr::from_ptr(name) }.to_str().ok()
+    }
+
+    /// Returns the name of the group used for the key exchange, e.g. `x25519`, `secp256r1` or `ffdhe2048`.
+    ///
+    /// The same as [`negotiated_group`](Self::negotiated_group) on OpenSSL 3.0 and later. On OpenSSL 1.1.1 the
+    /// group is derived from the peer's ephemeral key instead, naming elliptic curves only. Returns `None` before
+    /// the handshake, for a key exchange without ephemeral key, e.g. TLS 1.2 RSA, and before OpenSSL 1.1.1.
+    pub fn negotiated_group_name(&self) -> Option<String> {
+        #[cfg(ossl300)]
+        {
+            self.negotiated_group().map(str::to_owned)
+        }
+        #[cfg(all(ossl111, not(ossl300)))]
+        {
+            use openssl::pkey::Id;
+
+            let key = self.peer_tmp_key()?;
+            // the names `SSL_group_to_name` gives
+            let name = match key.id() {
+                Id::X25519 => "x25519",
+                Id::X448 => "x448",
+                Id::EC => match key.ec_key().ok()?.group().curve_name()? {
+                    Nid::X9_62_PRIME256V1 => "secp256r1",
+                    nid => nid.short_name().ok()?,
+                },
+                _ => return None,
+            };
+            Some(name.to_owned())
+        }
+        #[cfg(not(ossl111))]
+        None
+    }
+
+    /// Returns the algorithm the peer's certificate was compressed with (RFC 8879), e.g. `zlib`.
+    ///
+    /// That is the server certificate for a client, and the client certificate for a server. Returns `None` if
+    /// the certificate was not compressed, and always before OpenSSL 3.2.
+    ///
+    /// Reference: [`SSL_get_negotiated_server_cert_comp`](https://docs.openssl.org/master/man3/SSL_CTX_set1_cert_comp_preference/)
+    pub fn cert_compression_algorithm(&self) -> Option<&'static str> {
+        #[cfg(ossl320)]
+        {
+            let ssl = self.ssl().as_ptr();
+            // SAFETY: `ssl` is valid
+            let alg = unsafe {
+                if self.ssl().is_server() {
+                    ffi::SSL_get_negotiated_client_cert_comp(ssl)
+                } else {
+                    ffi::SSL_get_negotiated_server_cert_comp(ssl)
+                }
+            };
+            match alg {
+                ffi::TLSEXT_comp_cert_zlib => Some("zlib"),
+                ffi::TLSEXT_comp_cert_brotli => Some("brotli"),
+                ffi::TLSEXT_comp_cert_zstd => Some("zstd"),
+                _ => None,
+            }
+        }
+        #[cfg(not(ossl320))]
+        None
+    }
+
+    /// Requests a smaller maximum record size with the `record_size_limit` extension (RFC 8449).
+    ///
+    /// No OpenSSL release implements this extension, so this always fails with [`ErrorKind::Unsupported`].
+    /// Clients can request smaller records with [`set_max_fragment_length`](Self::set_max_fragment_length)
+    /// instead, which only defines four lengths.
+    pub fn set_record_size_limit(&mut self, limit: u16) -> io::Result<()> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("record size limit of {limit} bytes is not supported, OpenSSL lacks RFC 8449"),
+        ))
+    }
+
+    /// Requests a maximum record plaintext length with the `max_fragment_length` extension (RFC 6066).
+    ///
+    /// Only 512, 1024, 2048 and 4096 bytes are defined, any other length fails with [`ErrorKind::Unsupported`],
+    /// as does any length before OpenSSL 1.1.1. Must be called by the client before [`connect`](Self::connect);
+    /// servers honour the request automatically, see [`max_fragment_length`](Self::max_fragment_length).
+    ///
+    /// Records are then at most the length plus the protection overhead (up to 256 bytes), in both directions.
+    /// To save memory, the transport buffers can be shrunk accordingly by building the stream from
+    /// [`SyncStream::with_capacity`] and [`ssl::SslStream::new`], then converting it with [`SslStream::from`].
+    ///
+    /// Reference: [`SSL_set_tlsext_max_fragment_length`](https://docs.openssl.org/master/man3/SSL_CTX_set_split_send_fragment/)
+    pub fn set_max_fragment_length(&mut self, len: u16) -> io::Result<()> {
+        #[cfg(ossl111)]
+        if len.is_power_of_two() && (512..=4096).contains(&len) {
+            let mode = (len.trailing_zeros() - 8) as u8;
+            // SAFETY: `ssl` is valid and `mode` is one of the `TLSEXT_max_fragment_length_*` values
+            let ret = unsafe { ffi::SSL_set_tlsext_max_fragment_length(self.ssl().as_ptr(), mode) };
+            return if ret == 1 {
+                Ok(())
+            } else {
+                Err(ErrorStack::get().into())
+            };
+        }
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("max fragment length of {len} bytes is not supported"),
+        ))
+    }
+
+    /// Returns the maximum record plaintext length negotiated by the handshake, if any.
+    ///
+    /// See [`set_max_fragment_length`](Self::set_max_fragment_length). Always `None` before OpenSSL 1.1.1.
+    ///
+    /// Reference: [`SSL_SESSION_get_max_fragment_length`](https://docs.openssl.org/master/man3/SSL_CTX_set_split_send_fragment/)
+    pub fn max_fragment_length(&self) -> Option<u16> {
+        #[cfg(ossl111)]
+        if let Some(session) = self.ssl().session() {
+            // SAFETY: `session` is a valid pointer
+            let mode = unsafe { ffi::SSL_SESSION_get_max_fragment_length(session.as_ptr()) };
+            if (ffi::TLSEXT_max_fragment_length_512..=ffi::TLSEXT_max_fragment_length_4096).contains(&mode) {
+                return Some(256 << mode);
+            }
+        }
+        None
+    }
+
+    /// Initiates a server-side TLS handshake.
+    ///
+    /// Not cancel safe, see [cancel safety](SslStream#cancel-safety).
+    ///
+    /// Reference: [`SslStream::accept`](ssl::SslStream::accept)
+    pub async fn accept(&mut self) -> io::Result<()> {
+        self.ssl_async_do(|s| s.accept()).await
+    }
+
+    /// Initiates a client-side TLS handshake.
+    ///
+    /// Application data can be written once this returns. OpenSSL does not implement TLS False Start
+    /// (`SSL_MODE_ENABLE_FALSE_START` is specific to BoringSSL), so a full TLS 1.2 handshake takes two round
+    /// trips before the first write. TLS 1.3 takes one, and a resumption with
+    /// [`connect_with_early_data`](Self::connect_with_early_data) none.
+    ///
+    /// Not cancel safe, see [cancel safety](SslStream#cancel-safety).
+    ///
+    /// Reference: [`SslStream::connect`](ssl::SslStream::connect)
+    pub async fn connect(&mut self) -> io::Result<()> {
+        self.ssl_async_do(|s| s.connect()).await
+    }
+
+    /// Initiates a client-side TLS handshake and writes `first`, sending both in as few transport writes as
+    /// possible.
+    ///
+    /// When the client speaks last in the handshake, its final flight and the encrypted `first` leave in a
+    /// single transport write, saving a packet compared to [`connect`](Self::connect) followed by a write.
+    /// That is a TLS 1.3 handshake, and a resumed TLS 1.2 one. No round trip is saved: in a full TLS 1.2
+    /// handshake the client's final flight is sent before waiting for the server, so it cannot be combined.
+    ///
+    /// Returns the number of bytes of `first` written, which bypass
+    /// [`set_write_buffer_threshold`](Self::set_write_buffer_threshold).
+    pub async fn connect_and_write(&mut self, first: &[u8]) -> io::Result<usize> {
+        self.ssl_async_do_buffered(|s| s.connect()).await?;
+        let flush_on_write = std::mem::replace(&mut self.flush_on_write, false);
+        let ret = self.ssl_write(first).await;
+        self.flush_on_write = flush_on_write;
+        let n = ret?;
+        self.flush_transport().await?;
+        Ok(n)
+    }
+
+    /// Returns what the handshake waits for, or `None` once it is complete, without performing any I/O.
+    ///
+    /// This reflects the state after the most recent handshake attempt, e.g. a [`connect`](Self::connect)
+    /// cancelled while waiting for the server. Before the first attempt, a client wants to write its hello and
+    /// a server wants to read the client's.
+    #[cfg(ossl110)]
+    pub fn handshake_wants(&self) -> Option<HandshakeWant> {
+        if self.ssl().is_init_finished() {
+            return None;
+        }
+        match self.last_want {
+            Some(want) => Some(want),
+            None if self.ssl().is_server() => Some(HandshakeWant::WantRead),
+            None => Some(HandshakeWant::WantWrite),
+        }
+    }
+
+    /// Reads the ClientHello and returns what the client offered, without processing it any further.
+    ///
+    /// Meant for admission control and routing on servers: a connection for an unknown host name can be turned
+    /// away before the server spends any work on it. Afterwards, the ClientHello is held by OpenSSL, stopped in
+    /// the client hello callback. Nothing has been sent, and no session, key exchange or certificate has been
+    /// touched, so the SNI callback has not run either. Then either continue with [`accept`](Self::accept),
+    /// which processes the ClientHello as if there had been no pause, or reject the client, e.g. with
+    /// [`send_alert`](Self::send_alert) and [`Alert`] 112 (`unrecognized_name`), or by just dropping the stream.
+    ///
+    /// The context must be set up with [`enable_client_hello_peek`], otherwise [`ErrorKind::Unsupported`] is
+    /// returned. It must be called before the handshake starts.
+    #[cfg(ossl111)]
+    pub async fn peek_client_hello(&mut self) -> io::Result<ClientHelloInfo> {
+        if self.raw_bytes_read() > 0 || self.ssl().is_init_finished() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the handshake already started",
+            ));
+        }
+        if !client_hello::request_peek(self.ssl_mut()) {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "client hello peeking is not enabled on the context",
+            ));
+        }
+        self.check_interrupted()?;
+        self.ssl_mut().set_accept_state();
+        let mut rounds = self.io_rounds();
+        loop {
+            match self.stream.do_handshake() {
+                Err(e) if e.code() == ErrorCode::WANT_CLIENT_HELLO_CB => {
+                    return Ok(client_hello::client_hello_info(self.ssl()));
+                }
+                Err(e) if e.code() == ErrorCode::WANT_READ => {
+                    self.count_io_round(&mut rounds)?;
+                    self.fill_transport().await?;
+                }
+                Err(e) => return Err(self.ssl_err(e)),
+                Ok(()) => {
+                    return Err(io::Error::other(
+                        "handshake completed without a client hello callback",
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Performs a single step of the handshake, without waiting for the transport.
+    ///
+    /// This is the synchronous core of [`accept`](Self::accept) and [`connect`](Self::connect), for state
+    /// machines driving the transport themselves: on [`HandshakeStep::WantRead`], await
+    /// [`fill_read_buf`](Self::fill_read_buf), on [`HandshakeStep::WantWrite`],
+    /// [`flush_write_buf`](Self::flush_write_buf), and step again until [`HandshakeStep::Done`]. Without that,
+    /// stepping again makes no progress. Output of the handshake is only written to the transport buffer, so
+    /// `WantWrite` is also returned whenever the buffer holds bytes, even if OpenSSL waits for the peer or is
+    /// done.
+    ///
+    /// The role must be set beforehand with [`SslRef::set_connect_state`] or [`SslRef::set_accept_state`].
+    ///
+    /// Reference: [`SSL_do_handshake`](https://docs.openssl.org/master/man3/SSL_do_handshake/)
+    pub fn try_handshake(&mut self) -> io::Result<HandshakeStep> {
+        self.check_interrupted()?;
+        let want = match self.stream.do_handshake() {
+            Ok(()) => None,
+            Err(e) if e.code() == ErrorCode::WANT_READ => Some(HandshakeWant::WantRead),
+            Err(e) if e.code() == ErrorCode::WANT_WRITE => Some(HandshakeWant::WantWrite),
+            Err(e) => return Err(self.ssl_err(e)),
+        };
+        if want.is_some() {
+            self.last_want = want;
+        }
+        Ok(if self.pending_write_bytes() > 0 {
+            HandshakeStep::WantWrite
+        } else {
+            match want {
+                None => HandshakeStep::Done,
+                Some(HandshakeWant::WantRead) => HandshakeStep::WantRead,
+                Some(HandshakeWant::WantWrite) => HandshakeStep::WantWrite,
+            }
+        })
+    }
+
+    /// Reads from the transport into its buffer, for [`try_handshake`](Self::try_handshake).
+    ///
+    /// Returns the number of bytes read, `0` at EOF, where the handshake cannot complete.
+    pub async fn fill_read_buf(&mut self) -> io::Result<usize> {
+        self.fill_transport().await
+    }
+
+    /// Writes the transport buffer to the transport, for [`try_handshake`](Self::try_handshake).
+    ///
+    /// Returns the number of bytes written.
+    pub async fn flush_write_buf(&mut self) -> io::Result<usize> {
+        self.flush_transport().await
+    }
+
+    /// Like [`accept`](Self::accept), but aborts once `cancel` completes.
+    ///
+    /// On cancellation, `io::ErrorKind::Interrupted` is returned and the stream must be dropped, as the
+    /// handshake was interrupted in the middle. A handshake timeout is simply a `cancel` future that
+    /// completes after a delay, such as compio's `time::sleep`.
+    pub async fn accept_with_cancel<C: Future>(&mut self, cancel: C) -> io::Result<()> {
+        with_cancel(self.accept(), cancel).await
+    }
+
+    /// Like [`connect`](Self::connect), but aborts once `cancel` completes.
+    ///
+    /// See [`accept_with_cancel`](Self::accept_with_cancel) for details.
+    pub async fn connect_with_cancel<C: Future>(&mut self, cancel: C) -> io::Result<()> {
+        with_cancel(self.connect(), cancel).await
+    }
+
+    /// Read application data transmitted by a client before handshake completion.
+    ///
+    /// Useful for reducing latency, but vulnerable to replay attacks.
+    ///
+    /// Returns Ok(0) if all early data has been read. Fails with [`ErrorKind::Unsupported`] if the OpenSSL
+    /// library loaded at runtime is older than 1.1.1.
+    ///
+    /// Reference: [`SslStream::read_early_data`](ssl::SslStream::read_early_data)
+    #[cfg(any(ossl111, libressl340))]
+    pub async fn read_realy_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        require_runtime_version(0x1010_1000, "early data")?;
+        self.pace_read().await;
+        let n = self.ssl_async_do(|s| s.read_early_data(buf)).await?;
+        self.record_read(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Send data to the server without blocking on handshake completion.
+    ///
+    /// Useful for reducing latency, but vulnerable to replay attacks. Fails with [`ErrorKind::Unsupported`] if
+    /// the OpenSSL library loaded at runtime is older than 1.1.1.
+    ///
+    /// Reference: [`SslStream::write_early_data`](ssl::SslStream::write_early_data)
+    #[cfg(any(ossl111, libressl340))]
+    pub async fn write_realy_data(&mut self, buf: &[u8]) -> io::Result<usize> {
+        require_runtime_version(0x1010_1000, "early data")?;
+        self.pace_write().await;
+        let n = self.ssl_async_do(|s| s.write_early_data(buf)).await?;
+        self.record_written(n);
+        Ok(n)
+    }
+
+    /// Copies the master secret of the current session into `out`, returning the number of bytes written, at
+    /// most `out.len()`, or `0` without a session.
+    ///
+    /// This is for interoperability with legacy protocols deriving their own keys from the master secret only.
+    /// New designs must use an RFC 5705 exporter, [`SslRef::export_keying_material`], instead: it binds the
+    /// derived keys to a label and context, and never exposes the secret protecting the connection itself.
+    /// Anyone learning the master secret can decrypt the whole connection, so it must not be logged or sent
+    /// anywhere. TLS 1.3 has no master secret in the TLS 1.2 sense, OpenSSL returns the resumption secret there.
+    ///
+    /// Reference: [`SSL_SESSION_get_master_key`](https://docs.openssl.org/master/man3/SSL_get_client_random/)
+    pub fn master_key(&self, out: &mut [u8]) -> usize {
+        match self.ssl().session() {
+            // OpenSSL returns the full length for an empty buffer
+            Some(session) if !out.is_empty() => session.master_key(out),
+            _ => 0,
+        }
+    }
+
+    /// Returns how long the current session stays valid after its creation, or `None` without a session.
+    ///
+    /// That is the session timeout of the context, see [`set_session_timeout`]. For a client holding a session
+    /// ticket, it is capped by the lifetime the server announced with the ticket, which is what the server
+    /// enforces. TLS 1.3 tickets arrive after the handshake, with the first read. A client cache should not
+    /// offer the session for resumption once expired, see [`session_remaining`](Self::session_remaining).
+    ///
+    /// Reference: [`SSL_SESSION_get_timeout`](https://docs.openssl.org/master/man3/SSL_SESSION_get_time/),
+    /// [`SSL_SESSION_get_ticket_lifetime_hint`](https://docs.openssl.org/master/man3/SSL_SESSION_has_ticket/)
+    pub fn session_timeout(&self) -> Option<Duration> {
+        let session = self.ssl().session()?;
+        let timeout = session.timeout().max(0) as u64;
+        #[cfg(ossl110)]
+        // SAFETY: the session is valid
+        let timeout = match unsafe { ffi::SSL_SESSION_get_ticket_lifetime_hint(session.as_ptr()) } {
+            0 => timeout,
+            hint => timeout.min(hint as _),
+        };
+        Some(Duration::from_secs(timeout))
+    }
+
+    /// Returns when the current session was created, or `None` without a session.
+    ///
+    /// Reference: [`SSL_SESSION_get_time`](https://docs.openssl.org/master/man3/SSL_SESSION_get_time/)
+    pub fn session_created(&self) -> Option<SystemTime> {
+        let session = self.ssl().session()?;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(session.time() as u64))
+    }
+
+    /// Returns how long the current session remains valid from now, zero once expired, or `None` without a
+    /// session.
+    pub fn session_remaining(&self) -> Option<Duration> {
+        let expiry = self.session_created()? + self.session_timeout()?;
+        Some(expiry.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+    }
+
+    /// Returns how the handshake established its keys, telling apart the resumption mechanisms.
+    ///
+    /// Only meaningful once the handshake is complete. TLS 1.3 resumes sessions through tickets only, even
+    /// those of a server's session cache. A TLS 1.2 client tells from its session whether it held a ticket, but
+    /// OpenSSL gives a server no such indication: it reports [`ResumptionKind::Ticket`] unless tickets are
+    /// disabled on the connection, as OpenSSL only falls back to its session cache if the client offered none.
+    /// [`ResumptionKind::Psk`] is only reported for keys configured with [`set_psk_client`] or
+    /// [`set_psk_server`], and for TLS 1.2 PSK cipher suites.
+    ///
+    /// Reference: [`SSL_session_reused`](https://docs.openssl.org/master/man3/SSL_session_reused/)
+    pub fn resumption_kind(&self) -> ResumptionKind {
+        if self.psk_identity().is_some() {
+            return ResumptionKind::Psk;
+        }
+        if !self.ssl().session_reused() {
+            return ResumptionKind::Full;
+        }
+        if self.is_tlsv13() {
+            return ResumptionKind::Ticket;
+        }
+        let ticket = if self.ssl().is_server() {
+            !self.options().contains(SslOptions::NO_TICKET)
+        } else {
+            #[cfg(ossl111)]
+            {
+                // SAFETY: the session is valid
+                self.ssl()
+                    .session()
+                    .is_some_and(|session| unsafe { ffi::SSL_SESSION_has_ticket(session.as_ptr()) } == 1)
+            }
+            #[cfg(not(ossl111))]
+            false
+        };
+        if ticket {
+            ResumptionKind::Ticket
+        } else {
+            ResumptionKind::SessionId
+        }
+    }
+
+    /// Returns whether the server accepted the early data sent by [`write_realy_data`](Self::write_realy_data).
+    ///
+    /// Only meaningful once the handshake is complete. If `false`, the server discarded the early data, e.g.
+    /// because of its anti-replay protection, and it must be sent again as regular data if still needed.
+    ///
+    /// Reference: [`SSL_get_early_data_status`](https://docs.openssl.org/master/man3/SSL_read_early_data/)
+    #[cfg(any(ossl111, libressl340))]
+    pub fn early_data_accepted(&self) -> bool {
+        // SAFETY: the `Ssl` is valid
+        unsafe { ffi::SSL_get_early_data_status(self.ssl().as_ptr()) == ffi::SSL_EARLY_DATA_ACCEPTED }
+    }
+
+    /// Returns how many bytes of early data may be sent on this connection.
+    ///
+    /// For a client, that is the limit the server announced in the ticket of the session to resume, so it can
+    /// decide before [`connect`](Self::connect) whether a request fits, and write it normally otherwise. `0`
+    /// without a session or if the server does not accept early data. For a server, that is the limit it
+    /// accepts, as configured on the context or the `Ssl`.
+    ///
+    /// Reference: [`SSL_SESSION_get_max_early_data`](https://docs.openssl.org/master/man3/SSL_read_early_data/)
+    #[cfg(ossl111)]
+    pub fn max_early_data(&self) -> u32 {
+        if self.ssl().is_server() {
+            self.ssl().max_early_data()
+        } else {
+            self.ssl().session().map_or(0, |s| s.max_early_data())
+        }
+    }
+
+    /// Sends `data` as early data, completes the client-side handshake, and resends `data` as regular data
+    /// if the server rejected it.
+    ///
+    /// Early data is only sent if the resumed session allows it, and no more than the session's limit; the
+    /// rest follows the handshake. Returns whether the early data was accepted. Either way, `data` is received
+    /// by the server exactly once.
+    ///
+    /// Early data is not protected against replay: an attacker may record it and send it to the server again.
+    /// Only requests that are safe to process more than once, such as idempotent reads, should be passed here.
+    /// The resend is also done regardless of the negotiated application protocol, so callers whose request
+    /// depends on ALPN should check [`early_data_accepted`](Self::early_data_accepted) and resend themselves.
+    #[cfg(any(ossl111, libressl340))]
+    pub async fn connect_with_early_data(&mut self, data: &[u8]) -> io::Result<bool> {
+        let max = self.ssl().session().map_or(0, |s| s.max_early_data()) as usize;
+        let early = &data[..data.len().min(max)];
+        let mut written = 0;
+        while written < early.len() {
+            written += self.write_realy_data(&early[written..]).await?;
+        }
+        self.connect().await?;
+
+        let accepted = !early.is_empty() && self.early_data_accepted();
+        let mut written = if accepted { early.len() } else { 0 };
+        while written < data.len() {
+            written += self.ssl_write(&data[written..]).await?;
+        }
+        Ok(accepted)
+    }
+
+    /// Issues a new TLS 1.3 session ticket to the client, e.g. after a post-handshake authorization event.
+    ///
+    /// The ticket is sent immediately. The client only receives it once it reads from the stream. Fails with
+    /// [`ErrorKind::Unsupported`] if the OpenSSL library loaded at runtime is older than 3.0.
+    ///
+    /// Reference: [`SSL_new_session_ticket`](https://docs.openssl.org/master/man3/SSL_CTX_set_num_tickets/)
+    #[cfg(ossl300)]
+    pub async fn send_session_ticket(&mut self) -> io::Result<()> {
+        require_runtime_version(0x3000_0000, "sending session tickets")?;
+        // SAFETY: the `Ssl` is valid
+        if unsafe { ffi::SSL_new_session_ticket(self.ssl().as_ptr()) } != 1 {
+            return Err(ErrorStack::get().into());
+        }
+        // the ticket is queued until the next handshake or write call
+        self.ssl_async_do(|s| s.do_handshake()).await
+    }
+
+    /// Reads data into `buf` after its first `offset` bytes, e.g. to read a message body right after its header.
+    ///
+    /// On success, the initialized length of `buf` becomes `offset` plus the bytes read. Returns `Ok(0)` on EOF
+    /// or if there is no spare capacity after `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` exceeds the initialized length of `buf`.
+    pub async fn read_at_offset<B: IoBufMut>(&mut self, buf: B, offset: usize) -> BufResult<usize, B> {
+        let BufResult(ret, slice) = self.read(buf.slice(offset..)).await;
+        BufResult(ret, slice.into_inner())
+    }
+
+    /// Reads data from the stream, without removing it from the queue.
+    ///
+    /// Not cancel safe, see [cancel safety](SslStream#cancel-safety).
+    ///
+    /// Reference: [`SslStream::ssl_peek`](ssl::SslStream::ssl_peek)
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.ssl_async_do(|s| s.ssl_peek(buf)).await
+    }
+
+    /// Splits the stream into a read and a write half, e.g. to read and write from different tasks.
+    ///
+    /// Both directions share the state of the TLS connection, so the halves take turns: every operation holds
+    /// the stream until it completes. In particular, a read waiting for the peer holds back a write issued in
+    /// the meantime, so a request must be written before its response is awaited. The halves are joined
+    /// again with [`reunite`](Self::reunite).
+    pub fn split(self) -> (ReadHalf<S>, WriteHalf<S>) {
+        split::split(self)
+    }
+
+    /// Like [`split`](Self::split), but borrows the stream instead of consuming it.
+    ///
+    /// The halves can only be used while the stream is borrowed, e.g. in a `join!` of two futures, and
+    /// neither the stream nor `S` has to be `'static`.
+    pub fn split_borrowed(&mut self) -> (compio::io::ReadHalf<&mut Self>, compio::io::WriteHalf<&mut Self>) {
+        compio::io::split(self)
+    }
+
+    /// Reads the decrypted data line by line, see [`Lines`].
+    pub fn lines(self) -> Lines<S> {
+        Lines::new(self)
+    }
+
+    /// Wraps the stream for synchronous code, see [`BlockingStream`].
+    #[cfg(feature = "sync-compat")]
+    pub fn into_blocking(self) -> BlockingStream<S> {
+        BlockingStream::new(self)
+    }
+
+    /// Joins the halves created by [`split`](Self::split) back into the stream, e.g. to shut it down.
+    ///
+    /// Fails if the halves come from different streams, giving them back.
+    pub fn reunite(read_half: ReadHalf<S>, write_half: WriteHalf<S>) -> Result<Self, ReuniteError<S>>
+    where
+        S: Unpin,
+    {
+        split::reunite(read_half, write_half)
+    }
+
+    /// Waits until the stream has something to read, without reading it.
+    ///
+    /// Returns right away if decrypted data is pending, a complete record is buffered, or the peer closed the
+    /// connection. Otherwise, this waits for a single transport read. The bytes received may be a partial
+    /// record or one carrying no application data, e.g. a session ticket, so the wakeup can be spurious and
+    /// the next read may still wait. Nothing is consumed, the data stays available to the next read.
+    pub async fn readable(&mut self) -> io::Result<()> {
+        self.check_interrupted()?;
+        if self.ssl().pending() > 0 {
+            return Ok(());
+        }
+        match self.stream.ssl_peek(&mut [0; 1]) {
+            Ok(_) => Ok(()),
+            Err(e) if e.code() == ErrorCode::ZERO_RETURN => Ok(()),
+            Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => Ok(()),
+            Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => {
+                if self.flush_transport().await? == 0 {
+                    self.fill_transport().await?;
+                }
+                Ok(())
+            }
+            Err(e) => Err(self.ssl_err(e)),
+        }
+    }
+
+    /// Waits until the transport accepted all encrypted bytes buffered so far.
+    ///
+    /// compio is completion-based, so there is no readiness to wait for: this flushes the transport buffer,
+    /// see [`pending_write_bytes`](Self::pending_write_bytes), and returns right away if it is empty. Once it
+    /// returns, the next write starts with an empty buffer, so awaiting it between the chunks of a large
+    /// write paces them to the transport. Plaintext held back by
+    /// [`set_write_buffer_threshold`](Self::set_write_buffer_threshold) is left alone.
+    pub async fn writable(&mut self) -> io::Result<()> {
+        if self.pending_write_bytes() > 0 {
+            self.flush_transport().await?;
+        }
+        Ok(())
+    }
+
+    /// Encrypts the start of `buf` into a single record, leaving it in the transport buffer.
+    ///
+    /// Unlike [`write`](AsyncWrite::write), which sends every record to the transport right away, nothing is
+    /// flushed unless the transport buffer is full, so several calls followed by
+    /// [`flush_write_buf`](Self::flush_write_buf) send their records in one transport write. This allows
+    /// batching small messages deliberately, or shaping records for tests.
+    ///
+    /// At most one record's worth of `buf` is written, i.e. 16 KiB or the negotiated
+    /// [`max_fragment_length`](Self::max_fragment_length), and the number of bytes written is returned. If
+    /// `max_send_fragment` was lowered on the context, OpenSSL may still split it into several records.
+    /// Plaintext held back by [`set_write_buffer_threshold`](Self::set_write_buffer_threshold) is encrypted
+    /// first, so data stays in order.
+    pub async fn write_record(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.flush_plaintext().await?;
+        let limit = self.max_fragment_length().map_or(MAX_RECORD_PLAINTEXT, usize::from);
+        let flush_on_write = std::mem::replace(&mut self.flush_on_write, false);
+        let ret = self.ssl_write(&buf[..buf.len().min(limit)]).await;
+        self.flush_on_write = flush_on_write;
+        ret
+    }
+
+    /// Writes all of `req`, flushes it, then reads the start of the response into `resp`.
+    ///
+    /// Returns the number of bytes written and read. This is just a convenience over `write_all`, `flush`
+    /// and `read`, except that the encrypted request is flushed to the transport only once.
+    pub async fn write_then_read(&mut self, req: &[u8], resp: &mut [u8]) -> io::Result<(usize, usize)> {
+        self.check_interrupted()?;
+        self.pace_write().await;
+        self.flush_plaintext().await?;
+        let mut written = 0;
+        while written < req.len() {
+            // `written` only changes on success, so retries are given the same buffer
+            match self.stream.ssl_write(&req[written..]) {
+                Ok(n) => {
+                    written += n;
+                    self.record_written(n);
+                }
+                Err(e) if e.code() == ErrorCode::WANT_WRITE => {
+                    self.flush_transport().await?;
+                }
+                Err(e) => return Err(self.ssl_err(e)),
+            }
+        }
+        self.flush_transport().await?;
+
+        let read = self.read_slice(resp).await?;
+        Ok((written, read))
+    }
+
+    /// Reads until EOF, appending to `buf`, and fails with `io::ErrorKind::TimedOut` once no data arrived for
+    /// `idle`.
+    ///
+    /// The idle timer restarts on every read returning data. Unlike a timeout around `read_to_end`, `buf` is
+    /// returned with all bytes read so far even on failure, along with the number of bytes read or the error.
+    /// After a timeout the stream must be dropped, as a read was interrupted in the middle.
+    pub async fn read_to_end_timeout(
+        &mut self,
+        mut buf: Vec<u8>,
+        idle: Duration,
+    ) -> (io::Result<usize>, Vec<u8>) {
+        let start = buf.len();
+        loop {
+            let len = buf.len();
+            buf.resize(len + 16 * 1024, 0);
+            let deadline = Instant::now() + idle;
+            match with_deadline(self.read_slice(&mut buf[len..]), deadline).await {
+                Ok(0) => {
+                    buf.truncate(len);
+                    return (Ok(len - start), buf);
+                }
+                Ok(n) => buf.truncate(len + n),
+                Err(e) => {
+                    buf.truncate(len);
+                    return (Err(e), buf);
+                }
+            }
+        }
+    }
+
+    /// Reads into `buf` until at least `min` bytes are read, or EOF is reached.
+    ///
+    /// Returns the number of bytes read, which is less than `min` only on EOF, and may be up to `buf.len()`
+    /// if more data is already available. `min` is capped to `buf.len()`.
+    pub async fn read_at_least(&mut self, buf: &mut [u8], min: usize) -> io::Result<usize> {
+        let min = min.min(buf.len());
+        let mut filled = 0;
+        while filled < min {
+            match self.read_slice(&mut buf[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        Ok(filled)
+    }
+
+    /// Sends `size` bytes of `file` starting at `offset`, returning the number of bytes sent.
+    ///
+    /// Fewer bytes are sent only if the end of the file is reached. The file is read in chunks of the maximum
+    /// record size, each encrypted into a single record.
+    ///
+    /// There is no zero-copy path through `SSL_sendfile`: it needs kernel TLS, which OpenSSL only enables on
+    /// socket BIOs, while `SslStream` drives OpenSSL through a buffer.
+    pub async fn ssl_sendfile(&mut self, file: &File, offset: u64, size: u64) -> io::Result<u64> {
+        const CHUNK: usize = 16 * 1024;
+
+        self.flush_plaintext().await?;
+        let mut buf = Vec::with_capacity(CHUNK);
+        let mut sent = 0;
+        while sent < size {
+            let len = (size - sent).min(CHUNK as u64) as usize;
+            buf.clear();
+            let BufResult(ret, slice) = file.read_at(buf.slice(..len), offset + sent).await;
+            buf = slice.into_inner();
+            if ret? == 0 {
+                break;
+            }
+            let mut written = 0;
+            while written < buf.len() {
+                written += self.ssl_write(&buf[written..]).await?;
+            }
+            sent += buf.len() as u64;
+        }
+        Ok(sent)
+    }
+
+    /// Reads `src` until EOF and writes everything into the stream, returning the number of bytes copied.
+    ///
+    /// This is the encrypting half of a tunnel, [`copy_to`](Self::copy_to) the decrypting one. A single buffer
+    /// of the maximum record size is reused for all reads, each encrypted into records right away, and the
+    /// stream is flushed once `src` is exhausted. The stream is not shut down, so more can be written after.
+    ///
+    /// Errors of `src` and of the stream are returned as is, without the number of bytes copied before.
+    pub async fn copy_from<R: AsyncRead>(&mut self, src: &mut R) -> io::Result<u64> {
+        self.flush_plaintext().await?;
+        let mut buf = Vec::with_capacity(MAX_RECORD_PLAINTEXT);
+        let mut copied = 0;
+        loop {
+            buf.clear();
+            let BufResult(ret, read) = src.read(buf).await;
+            buf = read;
+            if ret? == 0 {
+                break;
+            }
+            let mut written = 0;
+            while written < buf.len() {
+                written += self.ssl_write(&buf[written..]).await?;
+            }
+            copied += buf.len() as u64;
+        }
+        self.flush().await?;
+        Ok(copied)
+    }
+
+    /// Reads the decrypted data until EOF and writes it into `dst`, returning the number of bytes copied.
+    ///
+    /// The decrypting counterpart of [`copy_from`](Self::copy_from). EOF is the peer's `close_notify`, or the
+    /// transport closing without one, see [`treat_close_notify_as_eof_error`](Self::treat_close_notify_as_eof_error).
+    /// `dst` is flushed at the end, but not shut down.
+    pub async fn copy_to<W: AsyncWrite>(&mut self, dst: &mut W) -> io::Result<u64> {
+        let mut buf = Vec::with_capacity(MAX_RECORD_PLAINTEXT);
+        let mut copied = 0;
+        loop {
+            buf.clear();
+            let BufResult(ret, read) = self.read(buf).await;
+            buf = read;
+            if ret? == 0 {
+                break;
+            }
+            let BufResult(ret, written) = dst.write_all(buf).await;
+            buf = written;
+            ret?;
+            copied += buf.len() as u64;
+        }
+        dst.flush().await?;
+        Ok(copied)
+    }
+
+    /// Decrypts as much data as is available into the free space of `ring`, so a proxy can forward it right
+    /// away.
+    ///
+    /// `ring` is used as a fixed-size ring buffer: the data is appended at its back, wrapping around the end of
+    /// its allocation, up to its current capacity, and it never reallocates. The caller makes room by
+    /// consuming from the front, e.g. writing out [`as_slices`](VecDeque::as_slices) and draining what was
+    /// written. OpenSSL decrypts straight into the ring, without an intermediate buffer.
+    ///
+    /// This waits only if no data is available, then keeps reading until OpenSSL needs more from the transport
+    /// or the ring is full, so a burst of records is taken in one call. Returns the number of bytes appended,
+    /// `Ok(0)` on EOF or if the ring is full. An error hit after some data was read is returned by the next
+    /// call.
+    ///
+    /// # Cancel safety
+    ///
+    /// The future must not be dropped before it completes, the stream is unusable afterwards and `ring` may be
+    /// left with zeroed bytes at its back.
+    pub async fn read_ring(&mut self, ring: &mut VecDeque<u8>) -> io::Result<usize> {
+        if let Some(e) = self.read_ring_error.take() {
+            return Err(e);
+        }
+        let start = ring.len();
+        if start == ring.capacity() {
+            return Ok(0);
+        }
+        ring.resize(ring.capacity(), 0);
+        let (front, back) = ring.as_mut_slices();
+        let (first, second) = if start < front.len() {
+            (&mut front[start..], back)
+        } else {
+            (&mut back[start - front.len()..], &mut [][..])
+        };
+
+        let mut filled = match self.read_slice(first).await {
+            Ok(n) => n,
+            Err(e) => {
+                ring.truncate(start);
+                return Err(e);
+            }
+        };
+        if filled > 0 {
+            loop {
+                let buf = match first.get_mut(filled..) {
+                    Some(buf) if !buf.is_empty() => buf,
+                    _ => &mut second[filled - first.len()..],
+                };
+                if buf.is_empty() {
+                    break;
+                }
+                // nothing awaited here, OpenSSL only decrypts what the transport buffer already holds
+                match self.stream.ssl_read(buf) {
+                    Ok(n) => {
+                        self.record_read(&buf[..n]);
+                        filled += n;
+                    }
+                    // more data is needed or the peer closed, the next call finds out again
+                    Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::ZERO_RETURN => break,
+                    Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => break,
+                    Err(e) => {
+                        self.read_ring_error = Some(self.ssl_err(e));
+                        break;
+                    }
+                }
+            }
+        }
+        ring.truncate(start + filled);
+        Ok(filled)
+    }
+
+    /// Performs a full two-way TLS shutdown: sends `close_notify`, waits for the peer's `close_notify` and shuts
+    /// down the underlying stream.
+    ///
+    /// [`AsyncWrite::shutdown`] does not wait for the peer, as many peers close the connection without replying,
+    /// which would block the caller until the transport is closed. Use this method when the peer's confirmation
+    /// matters, e.g. to reuse the transport afterwards or to make sure no truncation happened.
+    pub async fn shutdown_bidirectional(&mut self) -> io::Result<()> {
+        self.close_notify(true).await
     }
 
-    /// Get a mutable reference to the underlying stream.
+    /// Like [`shutdown_bidirectional`](Self::shutdown_bidirectional), but waits at most `dur` for the peer.
     ///
-    /// # Warning
+    /// Returns [`ShutdownOutcome::SentOnly`] if our `close_notify` was sent but the peer's did not arrive in
+    /// time, and fails with `io::ErrorKind::TimedOut` if not even ours could be sent. The underlying stream is
+    /// shut down either way, but the stream must be dropped after a timeout, as an I/O was interrupted.
+    pub async fn shutdown_timeout(&mut self, dur: Duration) -> io::Result<ShutdownOutcome> {
+        match with_deadline(self.close_notify(true), Instant::now() + dur).await {
+            Ok(()) => Ok(ShutdownOutcome::Clean),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                closed_by_peer(self.get_mut().shutdown().await)?;
+                if self.get_shutdown().contains(ShutdownState::SENT) && self.pending_write_bytes() == 0 {
+                    Ok(ShutdownOutcome::SentOnly)
+                } else {
+                    Err(e)
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attaches application data to the connection, e.g. a connection ID, reachable from OpenSSL callbacks
+    /// through [`ex_data`](crate::ex_data).
     ///
-    /// Any read/write operation to the stream would most likely corrupt the SSL session.
-    #[inline(always)]
-    pub fn get_mut(&mut self) -> &mut S {
-        self.stream.get_mut().get_mut()
+    /// See [`set_ex_data`](crate::set_ex_data) for the constraints on `T`.
+    pub fn set_ex_data<T: Send + Sync + 'static>(&mut self, value: T) {
+        ex_data::set_ex_data(self.ssl_mut(), value)
     }
 
-    /// Returns a shared reference to the underlying stream.
-    #[inline(always)]
-    pub fn get_ref(&self) -> &S {
-        self.stream.get_ref().get_ref()
+    /// Returns the application data of type `T` attached to the connection, if any.
+    pub fn ex_data<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        ex_data::ex_data(self.ssl())
     }
 
-    /// Returns a shared reference to the [`Ssl`] object associated with this stream.
+    /// Returns the session's shutdown state.
     #[inline(always)]
-    pub fn ssl(&self) -> &SslRef {
-        self.stream.ssl()
+    pub fn get_shutdown(&mut self) -> ShutdownState {
+        self.stream.get_shutdown()
     }
 
-    /// Initiates a server-side TLS handshake.
+    /// Returns a receiver of copies of the decrypted data read from now on, e.g. for another task logging the
+    /// first request line.
     ///
-    /// Reference: [`SslStream::accept`](ssl::SslStream::accept)
-    pub async fn accept(&mut self) -> io::Result<()> {
-        self.ssl_async_do(|s| s.accept()).await
+    /// Each read sends what it returned as one chunk, so the chunks add up to the data read since this call,
+    /// while this task keeps driving OpenSSL alone, unlike with [`split`](Self::split). Data only
+    /// [`peek`](Self::peek)ed at is not sent.
+    ///
+    /// Reads never wait for the observer. Up to 64 chunks are queued for it, and if it lags behind further,
+    /// its stream ends after them: it always sees the data from its start without gaps, but may not see all
+    /// of it. The stream also ends when this one is dropped, or replaced by calling `tee` again.
+    pub fn tee(&mut self) -> mpsc::Receiver<Vec<u8>> {
+        // one more slot is reserved for the sender
+        let (tx, rx) = mpsc::channel(TEE_CHUNKS - 1);
+        self.tee = Some(tx);
+        rx
     }
 
-    /// Initiates a server-side TLS handshake.
-    ///
-    /// Reference: [`SslStream::connect`](ssl::SslStream::connect)
-    pub async fn connect(&mut self) -> io::Result<()> {
-        self.ssl_async_do(|s| s.connect()).await
+    /// Returns the number of plaintext bytes read over the lifetime of the connection.
+    #[inline(always)]
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
     }
 
-    /// Read application data transmitted by a client before handshake completion.
+    /// Returns the number of plaintext bytes written over the lifetime of the connection.
     ///
-    /// Useful for reducing latency, but vulnerable to replay attacks.
-    ///
-    /// Returns Ok(0) if all early data has been read.
+    /// Bytes held back by [`set_write_buffer_threshold`](Self::set_write_buffer_threshold) are only counted
+    /// once they are encrypted.
+    #[inline(always)]
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the number of encrypted bytes received from the transport, including the handshake.
     ///
-    /// Reference: [`SslStream::read_early_data`](ssl::SslStream::read_early_data)
-    #[cfg(any(ossl111, libressl340))]
-    pub async fn read_realy_data(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.ssl_async_do(|s| s.read_early_data(buf)).await
+    /// Reference: [`BIO_number_read`](https://docs.openssl.org/master/man3/BIO_read/)
+    pub fn raw_bytes_read(&self) -> u64 {
+        // SAFETY: the `Ssl` always has a BIO, the one wrapping the transport
+        unsafe { ffi::BIO_number_read(ffi::SSL_get_rbio(self.ssl().as_ptr())) }
     }
 
-    /// Send data to the server without blocking on handshake completion.
+    /// Returns the number of encrypted bytes sent to the transport, including the handshake.
     ///
-    /// Useful for reducing latency, but vulnerable to replay attacks.
+    /// Bytes still in the transport buffer are counted, as they are already handed over by OpenSSL.
     ///
-    /// Reference: [`SslStream::write_early_data`](ssl::SslStream::write_early_data)
-    #[cfg(any(ossl111, libressl340))]
-    pub async fn write_realy_data(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.ssl_async_do(|s| s.write_early_data(buf)).await
+    /// Reference: [`BIO_number_written`](https://docs.openssl.org/master/man3/BIO_read/)
+    pub fn raw_bytes_written(&self) -> u64 {
+        // SAFETY: the `Ssl` always has a BIO, the one wrapping the transport
+        unsafe { ffi::BIO_number_written(ffi::SSL_get_wbio(self.ssl().as_ptr())) }
     }
 
-    /// Reads data from the stream, without removing it from the queue.
+    /// Returns the last fatal alert received from the peer, if any.
+    #[inline(always)]
+    pub fn last_alert(&self) -> Option<Alert> {
+        self.last_alert
+    }
+
+    /// Clears OpenSSL's error queue of the current thread and forgets the [`last_alert`](Self::last_alert).
     ///
-    /// Reference: [`SslStream::ssl_peek`](ssl::SslStream::ssl_peek)
-    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.ssl_async_do(|s| s.ssl_peek(buf)).await
+    /// OpenSSL reports failures through a per-thread queue, and takes anything left in it for an error of the
+    /// next operation, so that even a pending read fails then. Failing operations of `SslStream` drain it, but
+    /// other code on the thread may leave errors behind, e.g. a raw `SSL_*` call through [`ssl_mut`](Self::ssl_mut)
+    /// or an FFI library. Call this before continuing after such code failed.
+    ///
+    /// It is safe to continue after failures that left the connection intact, such as misuse reported with
+    /// [`ErrorKind::InvalidInput`], or [`ErrorKind::Unsupported`]. Clearing does not make a broken connection
+    /// usable again: after a TLS error, a fatal alert, or a timeout or cancellation interrupting an operation,
+    /// even one merely waiting for data, the stream must still be dropped, as the transport buffer is lost with
+    /// the cancelled I/O.
+    ///
+    /// Reference: [`ERR_clear_error`](https://docs.openssl.org/master/man3/ERR_clear_error/)
+    pub fn clear_error(&mut self) {
+        // SAFETY: takes no arguments, and only touches the queue of the current thread
+        unsafe { ffi::ERR_clear_error() };
+        self.last_alert = None;
     }
 
-    /// Returns the session's shutdown state.
-    #[inline(always)]
-    pub fn get_shutdown(&mut self) -> ShutdownState {
-        self.stream.get_shutdown()
+    /// Sends the fatal `alert` to the peer and closes the transport, aborting the connection with a reason.
+    ///
+    /// OpenSSL has no API to send an arbitrary alert, so this is only possible before anything was sent: the
+    /// alert then goes out as a plaintext record, e.g. from a server turning a client away before the
+    /// handshake. Once records are encrypted, nothing is sent and [`ErrorKind::Unsupported`] is returned;
+    /// [`shutdown`](AsyncWrite::shutdown) is the closest alternative, closing with `close_notify`.
+    ///
+    /// During the handshake, OpenSSL sends the alerts itself, e.g. the one a client hello callback or a verify
+    /// callback fails with.
+    ///
+    /// The stream cannot be used afterwards.
+    pub async fn send_alert(&mut self, alert: Alert) -> io::Result<()> {
+        if self.raw_bytes_written() > 0 {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "alerts can only be sent before the first record",
+            ));
+        }
+        // level fatal, with the record version of TLS 1.2 which every version accepts
+        let record = [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, alert.code()];
+        // SAFETY: the `Ssl` always has a BIO, writing to the empty transport buffer
+        let n = unsafe {
+            let bio = ffi::SSL_get_wbio(self.ssl().as_ptr());
+            ffi::BIO_write(bio, record.as_ptr().cast(), record.len() as _)
+        };
+        if n != record.len() as _ {
+            return Err(io::Error::other("failed to buffer the alert"));
+        }
+        self.set_shutdown(ShutdownState::SENT);
+        closed_by_peer(self.flush_transport().await)?;
+        closed_by_peer(self.stream.get_mut().get_mut().shutdown().await)
     }
 
     /// Sets the session's shutdown state.
@@ -119,66 +1742,686 @@ impl<S: AsyncRead + AsyncWrite> SslStream<S> {
     /// was read, in which case the handshake should be continued via
     /// `accept`. If a HelloRetryRequest containing a fresh cookie was
     /// transmitted, `Ok(false)` is returned instead. If the handshake cannot
-    /// proceed at all, `Err` is returned.
+    /// proceed at all, `Err` is returned, with [`ErrorKind::Unsupported`] if
+    /// the OpenSSL library loaded at runtime is older than 1.1.1.
     #[inline(always)]
     #[cfg(ossl111)]
-    pub async fn stateless(&mut self) -> Result<bool, ErrorStack> {
-        self.stream.stateless()
+    pub async fn stateless(&mut self) -> io::Result<bool> {
+        require_runtime_version(0x1010_1000, "stateless handshakes")?;
+        Ok(self.stream.stateless()?)
+    }
+
+    /// Flushes the encrypted bytes in the transport buffer, keeping track for `pending_write_bytes`.
+    async fn flush_transport(&mut self) -> io::Result<usize> {
+        self.check_interrupted()?;
+        self.transport_busy = true;
+        let n = self.stream.get_mut().flush_write_buf().await;
+        self.transport_busy = false;
+        let n = n?;
+        // the whole buffer went out, including bytes written before the stream was wrapped
+        self.flushed = self.raw_bytes_written();
+        Ok(n)
+    }
+
+    async fn ssl_write(&mut self, slice: &[u8]) -> io::Result<usize> {
+        // Without `SSL_MODE_ACCEPT_MOVING_WRITE_BUFFER`, OpenSSL requires a retried `SSL_write` to be given
+        // the exact same buffer, so `slice` must stay unchanged across the retries below.
+        self.check_interrupted()?;
+        self.pace_write().await;
+        loop {
+            match self.stream.ssl_write(slice) {
+                Ok(n) => {
+                    self.record_written(n);
+                    if self.flush_on_write {
+                        self.flush_transport().await?;
+                    }
+                    return Ok(n);
+                }
+                Err(e) if e.code() == ErrorCode::WANT_WRITE => {
+                    self.flush_transport().await?;
+                }
+                Err(e) => return Err(self.ssl_err(e)),
+            }
+        }
+    }
+
+    /// Reads into `buf`, returning `Ok(0)` on EOF.
+    async fn read_slice(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pace_read().await;
+        let n = self
+            .ssl_async_do(|s| match s.ssl_read(buf) {
+                Err(e) if e.code() == ErrorCode::ZERO_RETURN => Ok(None),
+                Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => Ok(Some(0)),
+                ret => ret.map(Some),
+            })
+            .await?;
+        let Some(n) = n else {
+            return self.zero_return();
+        };
+        self.record_read(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Writes `slice`, or buffers it according to [`set_write_buffer_threshold`](Self::set_write_buffer_threshold).
+    ///
+    /// The future must complete for `slice` to stay the same across `SSL_write` retries.
+    async fn write_slice(&mut self, slice: &[u8]) -> io::Result<usize> {
+        if self.write_threshold == 0 {
+            // Small writes take no allocation and a single transport write here, within a few percent of plain
+            // `SSL_write` and flushing, as measured by `small_write_overhead_test`.
+            return self.ssl_write(slice).await;
+        }
+
+        self.write_buf.extend_from_slice(slice);
+        if self.write_buf.len() >= self.write_threshold {
+            self.flush_plaintext().await?;
+        }
+        Ok(slice.len())
+    }
+
+    /// Encrypts and sends all buffered plaintext.
+    async fn flush_plaintext(&mut self) -> io::Result<()> {
+        let mut pending = std::mem::take(&mut self.write_buf);
+        let mut written = 0;
+        let mut ret = Ok(());
+        while written < pending.len() {
+            match self.ssl_write(&pending[written..]).await {
+                Ok(n) => written += n,
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+        }
+        // keep what could not be sent, and the allocation
+        pending.drain(..written);
+        self.write_buf = pending;
+        ret
+    }
+
+    /// Sends `close_notify`, optionally waits for the peer's, and shuts down the underlying stream.
+    async fn close_notify(&mut self, wait_peer: bool) -> io::Result<()> {
+        self.check_interrupted()?;
+        self.flush_plaintext().await?;
+        loop {
+            let ret = self.stream.shutdown();
+            match ret {
+                Ok(ShutdownResult::Sent) => {
+                    let flushed = self.flush_transport().await;
+                    if !wait_peer {
+                        // the peer may have closed the transport without waiting for us either
+                        closed_by_peer(flushed)?;
+                        break;
+                    }
+                    flushed?;
+                }
+                Ok(ShutdownResult::Received) => {
+                    // our `close_notify` may have been written by this very call
+                    closed_by_peer(self.flush_transport().await)?;
+                    break;
+                }
+                Err(e) if e.code() == ErrorCode::WANT_WRITE => {
+                    self.flush_transport().await?;
+                }
+                Err(e) if e.code() == ErrorCode::WANT_READ => {
+                    // a read already reached the peer's `close_notify`, there is nothing left to wait for
+                    if self.stream.get_shutdown().contains(ShutdownState::RECEIVED) {
+                        break;
+                    }
+                    // the peer closed the transport without `close_notify`, our side is done anyway
+                    if self.fill_transport().await? == 0 {
+                        break;
+                    }
+                }
+                Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => {
+                    break;
+                }
+                Err(e) => return Err(self.ssl_err(e)),
+            }
+        }
+        closed_by_peer(self.stream.get_mut().get_mut().shutdown().await)
+    }
+
+    async fn ssl_async_do<R, F>(&mut self, f: F) -> io::Result<R>
+    where
+        F: FnMut(&mut ssl::SslStream<SyncStream<S>>) -> Result<R, ssl::Error>,
+    {
+        let ret = self.ssl_async_do_buffered(f).await?;
+        // the final handshake flight may still sit in the write buffer
+        self.flush_transport().await?;
+        Ok(ret)
     }
 
-    async fn ssl_async_do<R, F>(&mut self, mut f: F) -> io::Result<R>
+    /// Like [`ssl_async_do`](Self::ssl_async_do), but leaves what `f` wrote last in the transport buffer.
+    async fn ssl_async_do_buffered<R, F>(&mut self, mut f: F) -> io::Result<R>
     where
         F: FnMut(&mut ssl::SslStream<SyncStream<S>>) -> Result<R, ssl::Error>,
     {
+        self.check_interrupted()?;
+        let mut rounds = self.io_rounds();
         loop {
             match f(&mut self.stream) {
                 Ok(n) => return Ok(n),
                 Err(e) => match e.code() {
                     ErrorCode::WANT_READ | ErrorCode::WANT_WRITE => {
-                        if self.stream.get_mut().flush_write_buf().await? == 0 {
-                            self.stream.get_mut().fill_read_buf().await?;
+                        self.count_io_round(&mut rounds)?;
+                        self.last_want = Some(if e.code() == ErrorCode::WANT_READ {
+                            HandshakeWant::WantRead
+                        } else {
+                            HandshakeWant::WantWrite
+                        });
+                        // OpenSSL consumes partial records from the read buffer and keeps them itself, so a
+                        // `WANT_READ` always means the buffer is drained. Retrying `f` is thus cheap, and every
+                        // fill is a transport read that is actually needed to complete the record.
+                        if self.flush_transport().await? == 0 {
+                            self.fill_transport().await?;
+                        } else {
+                            rounds.count = 0;
                         }
                     }
-                    _ => return Err(ssl_err_into_io(e)),
+                    #[cfg(ossl111)]
+                    ErrorCode::WANT_CLIENT_HELLO_CB => match client_hello::take_resume(self.ssl_mut()) {
+                        Some(resume) => resume.await,
+                        None => {
+                            return Err(io::Error::other(
+                                "client hello callback paused the handshake without `pause_handshake`",
+                            ));
+                        }
+                    },
+                    #[cfg(ossl111)]
+                    code if code.as_raw() == ffi::SSL_ERROR_WANT_X509_LOOKUP => {
+                        match client_hello::take_resume(self.ssl_mut()) {
+                            Some(resume) => resume.await,
+                            None => {
+                                return Err(io::Error::other(
+                                    "certificate callback paused the handshake without `pause_cert_lookup`",
+                                ));
+                            }
+                        }
+                    }
+                    #[cfg(ossl300)]
+                    code if code.as_raw() == ffi::SSL_ERROR_WANT_RETRY_VERIFY => {
+                        match client_hello::take_resume(self.ssl_mut()) {
+                            Some(resume) => resume.await,
+                            None => {
+                                return Err(io::Error::other(
+                                    "verify callback paused the handshake without `pause_verification`",
+                                ));
+                            }
+                        }
+                    }
+                    _ => {
+                        // let the peer know why, e.g. through an alert
+                        let _ = self.flush_transport().await;
+                        return Err(self.ssl_err(e));
+                    }
                 },
             }
         }
     }
 }
 
+/// The transport rounds an operation waited for since it last made progress.
+struct IoRounds {
+    count: usize,
+    state: c_int,
+}
+
+impl<S> SslStream<S> {
+    /// Starts counting the transport rounds of an operation, see [`set_max_io_rounds`](Self::set_max_io_rounds).
+    fn io_rounds(&self) -> IoRounds {
+        IoRounds {
+            count: 0,
+            state: self.handshake_state(),
+        }
+    }
+
+    /// Counts a transport round an operation waits for, failing past the limit unless it made progress.
+    fn count_io_round(&self, rounds: &mut IoRounds) -> io::Result<()> {
+        let state = self.handshake_state();
+        if state != rounds.state {
+            // a handshake message was completed
+            rounds.state = state;
+            rounds.count = 0;
+        }
+        rounds.count += 1;
+        match self.max_io_rounds {
+            Some(max) if rounds.count > max => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "peer data arrives in too many pieces",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    #[inline(always)]
+    fn handshake_state(&self) -> c_int {
+        #[cfg(ossl110)]
+        // SAFETY: the `Ssl` is valid
+        return unsafe { ffi::SSL_get_state(self.stream.ssl().as_ptr()) };
+        #[cfg(not(ossl110))]
+        0
+    }
+
+    /// Fails if an operation was dropped while waiting for the transport, see
+    /// [cancel safety](SslStream#cancel-safety).
+    ///
+    /// The transport buffer was handed to the pending I/O then, and is gone: continuing would panic.
+    fn check_interrupted(&self) -> io::Result<()> {
+        if self.transport_busy {
+            return Err(io::Error::other(
+                "an operation was cancelled while waiting for the transport, the stream must be dropped",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads from the transport into the transport buffer.
+    async fn fill_transport(&mut self) -> io::Result<usize>
+    where
+        S: AsyncRead,
+    {
+        self.check_interrupted()?;
+        self.transport_busy = true;
+        let n = self.stream.get_mut().fill_read_buf().await;
+        self.transport_busy = false;
+        n
+    }
+
+    /// Accounts for decrypted bytes read into `data`, and sends a copy to the [`tee`](Self::tee) observer.
+    fn record_read(&mut self, data: &[u8]) {
+        self.bytes_read += data.len() as u64;
+        if let Some(limit) = &mut self.read_limit {
+            limit.consume(data.len());
+        }
+        if let Some(tee) = &mut self.tee
+            && !data.is_empty()
+            && tee.try_send(data.to_vec()).is_err()
+        {
+            // lagging behind or gone, end the observer's stream rather than leave a gap in it
+            self.tee = None;
+        }
+    }
+
+    /// Accounts for `n` plaintext bytes written.
+    fn record_written(&mut self, n: usize) {
+        self.bytes_written += n as u64;
+        if let Some(limit) = &mut self.write_limit {
+            limit.consume(n);
+        }
+    }
+
+    /// Waits for the [read rate limit](Self::set_read_rate_limit), if any.
+    async fn pace_read(&mut self) {
+        if let Some(limit) = &mut self.read_limit {
+            limit.acquire().await;
+        }
+    }
+
+    /// Waits for the [write rate limit](Self::set_write_rate_limit), if any.
+    async fn pace_write(&mut self) {
+        if let Some(limit) = &mut self.write_limit {
+            limit.acquire().await;
+        }
+    }
+
+    /// The result of a read finding the peer's `close_notify`.
+    fn zero_return(&self) -> io::Result<usize> {
+        if self.close_notify_eof_error {
+            Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "peer closed the connection before all data arrived",
+            ))
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn ssl_err(&mut self, e: ssl::Error) -> io::Error {
+        if let Some(alert) = Alert::from_error(&e) {
+            self.last_alert = Some(alert);
+        }
+        ssl_err_into_io(e)
+    }
+}
+
+#[cfg(unix)]
+impl SslStream<TcpStream> {
+    /// Create a new `SslStream` over a connected TCP socket given as a raw file descriptor, e.g. one received
+    /// through systemd socket activation or from an accept loop outside of Rust.
+    ///
+    /// The socket is registered to the current compio runtime, so this must be called within one.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be a valid, open and connected TCP socket, owned by the caller and not used elsewhere
+    /// afterwards, as the returned stream takes ownership of it and closes it on drop. It must allow
+    /// non-blocking operation, which the stream may switch it to.
+    pub unsafe fn from_raw_fd(ssl: Ssl, fd: RawFd) -> io::Result<SslStream<TcpStream>> {
+        // SAFETY: upheld by the caller
+        let stream = unsafe { std::net::TcpStream::from_raw_fd(fd) };
+        Ok(SslStream::new(ssl, TcpStream::from_std(stream)?)?)
+    }
+}
+
+/// Wraps an OpenSSL stream over a [`SyncStream`], e.g. one built with a custom buffer capacity.
+///
+/// Bytes already in the read buffer of the `SyncStream` are consumed by OpenSSL before the transport is read
+/// again. A router may thus read the start of a connection into it, inspect the first record with
+/// [`BufRead::fill_buf`](std::io::BufRead::fill_buf) without consuming it, and hand the stream over for
+/// [`accept`](SslStream::accept), which sees the ClientHello from its first byte.
 impl<S> From<ssl::SslStream<SyncStream<S>>> for SslStream<S> {
     fn from(value: ssl::SslStream<SyncStream<S>>) -> Self {
-        SslStream { stream: value }
+        // SAFETY: the `Ssl` always has a BIO, the one wrapping the transport
+        let flushed = unsafe { ffi::BIO_number_written(ffi::SSL_get_wbio(value.ssl().as_ptr())) };
+        SslStream {
+            stream: value,
+            write_buf: Vec::new(),
+            write_threshold: 0,
+            flush_on_write: true,
+            flushed,
+            last_alert: None,
+            bytes_read: 0,
+            bytes_written: 0,
+            last_want: None,
+            max_io_rounds: None,
+            close_notify_eof_error: false,
+            transport_busy: false,
+            tee: None,
+            read_limit: None,
+            write_limit: None,
+            read_ring_error: None,
+        }
+    }
+}
+
+/// Enables OpenSSL's in-memory server-side session cache, holding up to `size` sessions.
+///
+/// This allows session ID resumption in TLS 1.2 for clients not supporting session tickets, and stateful
+/// resumption in TLS 1.3 when tickets are disabled with [`SslOptions::NO_TICKET`](ssl::SslOptions::NO_TICKET).
+/// `id_context` distinguishes sessions of different contexts or applications sharing the cache.
+///
+/// For an external cache, e.g. shared between processes, register the new/get/remove session callbacks of
+/// [`SslContextBuilder`] instead.
+///
+/// Reference: [`SslContextBuilder::set_session_cache_mode`]
+pub fn enable_session_cache(
+    builder: &mut SslContextBuilder,
+    id_context: &[u8],
+    size: i32,
+) -> Result<(), ErrorStack> {
+    builder.set_session_cache_mode(SslSessionCacheMode::SERVER);
+    builder.set_session_id_context(id_context)?;
+    builder.set_session_cache_size(size);
+    Ok(())
+}
+
+/// Sets how long sessions created with the context stay valid, two hours by default.
+///
+/// On a server, this is also the lifetime announced to clients with session tickets. Sessions are not resumed
+/// after expiry, neither from tickets nor from the cache of [`enable_session_cache`]. Being a setting of the
+/// context, it is applied to the builder, e.g. the [`SslAcceptorBuilder`](ssl::SslAcceptorBuilder) of a
+/// [`TlsAcceptor`], before the context is shared between connections.
+///
+/// Reference: [`SSL_CTX_set_timeout`](https://docs.openssl.org/master/man3/SSL_CTX_set_timeout/)
+pub fn set_session_timeout(builder: &mut SslContextBuilder, timeout: Duration) {
+    // SAFETY: the context is valid
+    unsafe { ffi::SSL_CTX_set_timeout(builder.as_ptr(), timeout.as_secs() as _) };
+}
+
+/// Returns whether OpenSSL runs in FIPS mode, restricting all streams to FIPS approved algorithms.
+///
+/// With OpenSSL 3.0 and later, that is when the FIPS provider is loaded and the default properties of the
+/// default library context ask for `fips=yes`. Both are best set up in the OpenSSL configuration, see
+/// [`fips_module`](https://docs.openssl.org/master/man7/fips_module/), or otherwise before creating any context,
+/// e.g. with [`Provider::load`](openssl::provider::Provider::load) and `EVP_default_properties_enable_fips`.
+/// Note that X25519 and ChaCha20-Poly1305 are not approved, so e.g. `mozilla_modern_v5` needs adjustment.
+///
+/// Before OpenSSL 3.0, this reports [`FIPS_mode`](openssl::fips::enabled).
+pub fn is_fips() -> bool {
+    #[cfg(ossl300)]
+    {
+        fips_enabled(std::ptr::null_mut())
+    }
+    #[cfg(not(any(ossl300, libressl340)))]
+    {
+        openssl::fips::enabled()
+    }
+    #[cfg(libressl340)]
+    false
+}
+
+/// Returns whether `ctx`, null for the default one, fetches algorithms from the FIPS provider.
+#[cfg(ossl300)]
+fn fips_enabled(ctx: *mut ffi::OSSL_LIB_CTX) -> bool {
+    // SAFETY: `ctx` is valid or null, and the name is NUL-terminated
+    unsafe {
+        ffi::EVP_default_properties_is_fips_enabled(ctx) == 1
+            && ffi::OSSL_PROVIDER_available(ctx, c"fips".as_ptr()) == 1
+    }
+}
+
+/// Fails with [`ErrorKind::Unsupported`] if the OpenSSL library loaded at runtime is older than `min`, an
+/// `OPENSSL_VERSION_NUMBER` such as `0x1010_1000` for 1.1.1 or `0x3000_0020` for 3.0.2.
+///
+/// The cfgs set by `build.rs` reflect the headers the crate was built against, while a dynamically linked
+/// library may be older, e.g. when deploying to another system.
+fn require_runtime_version(min: i64, feature: &str) -> io::Result<()> {
+    check_version(openssl::version::number(), min, feature)
+}
+
+fn check_version(runtime: i64, min: i64, feature: &str) -> io::Result<()> {
+    if runtime >= min {
+        return Ok(());
+    }
+    let (major, minor) = ((min >> 28) & 0xff, (min >> 20) & 0xff);
+    // `0xMNN00PP0` since 3.0, `0xMNNFFPPS` before, with the letter release in `PP`
+    let patch = if major >= 3 {
+        (min >> 4) & 0xff
+    } else {
+        (min >> 12) & 0xff
+    };
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "{feature} requires OpenSSL {major}.{minor}.{patch} or later, but {} is loaded",
+            openssl::version::version()
+        ),
+    ))
+}
+
+/// Registers a server-side ALPN selection function on the context.
+///
+/// `select` receives the protocols offered by the client, in the client's order, and returns the chosen one.
+/// Returning `None` leaves the connection without a negotiated protocol.
+///
+/// Works with [`SslAcceptorBuilder`](ssl::SslAcceptorBuilder) through deref.
+///
+/// Reference: [`SslContextBuilder::set_alpn_select_callback`]
+pub fn set_alpn_select<F>(builder: &mut SslContextBuilder, select: F)
+where
+    F: for<'a> Fn(&[&'a [u8]]) -> Option<&'a [u8]> + 'static + Sync + Send,
+{
+    builder.set_alpn_select_callback(move |_, client| {
+        let offered = parse_alpn(client).ok_or(AlpnError::ALERT_FATAL)?;
+        select(&offered).ok_or(AlpnError::NOACK)
+    });
+}
+
+/// The identity of the external PSK of a connection, recorded by [`set_psk_client`] and [`set_psk_server`].
+struct PskIdentity(Vec<u8>);
+
+/// Configures a client to authenticate with the external pre-shared `key` named `identity`, instead of
+/// certificates.
+///
+/// With TLS 1.3, the key is used with a SHA-256 cipher suite, e.g. `TLS_AES_128_GCM_SHA256`; with TLS 1.2, a
+/// PSK cipher suite such as `PSK-AES128-GCM-SHA256` has to be enabled. The identity can be read back with
+/// [`SslStream::psk_identity`]. The handshake fails if the identity is longer than 128 bytes.
+///
+/// Reference: [`SslContextBuilder::set_psk_client_callback`]
+pub fn set_psk_client(builder: &mut SslContextBuilder, identity: &[u8], key: &[u8]) {
+    let (identity, key) = (identity.to_vec(), key.to_vec());
+    builder.set_psk_client_callback(move |ssl, _, identity_buf, psk_buf| {
+        // the identity is NUL-terminated
+        if identity.len() >= identity_buf.len() || key.len() > psk_buf.len() {
+            return Err(ErrorStack::get());
+        }
+        identity_buf[..identity.len()].copy_from_slice(&identity);
+        identity_buf[identity.len()] = 0;
+        psk_buf[..key.len()].copy_from_slice(&key);
+        ex_data::set_ex_data(ssl, PskIdentity(identity.clone()));
+        Ok(key.len())
+    });
+}
+
+/// Configures a server to authenticate clients with external pre-shared keys, which `lookup` returns by
+/// identity. The handshake fails for unknown identities.
+///
+/// The server needs no certificate then. See [`set_psk_client`] for the cipher suites.
+///
+/// Reference: [`SslContextBuilder::set_psk_server_callback`]
+pub fn set_psk_server<F>(builder: &mut SslContextBuilder, lookup: F)
+where
+    F: Fn(&[u8]) -> Option<Vec<u8>> + 'static + Sync + Send,
+{
+    builder.set_psk_server_callback(move |ssl, identity, psk_buf| {
+        let identity = identity.ok_or_else(ErrorStack::get)?;
+        let key = lookup(identity).ok_or_else(ErrorStack::get)?;
+        if key.len() > psk_buf.len() {
+            return Err(ErrorStack::get());
+        }
+        psk_buf[..key.len()].copy_from_slice(&key);
+        ex_data::set_ex_data(ssl, PskIdentity(identity.to_vec()));
+        Ok(key.len())
+    });
+}
+
+/// Splits the ALPN protocol list of a ClientHello, `None` if malformed.
+fn parse_alpn(client: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut offered = Vec::new();
+    let mut rest = client;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = len as usize;
+        if len == 0 || len > tail.len() {
+            return None;
+        }
+        let (proto, tail) = tail.split_at(len);
+        offered.push(proto);
+        rest = tail;
+    }
+    Some(offered)
+}
+
+/// Drives `fut` until it completes, or fails with `Interrupted` once `cancel` completes first.
+async fn with_cancel<T, F, C>(fut: F, cancel: C) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+    C: Future,
+{
+    let mut fut = pin!(fut);
+    let mut cancel = pin!(cancel);
+    poll_fn(|cx| {
+        if cancel.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(io::Error::new(ErrorKind::Interrupted, "handshake cancelled")));
+        }
+        fut.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// Runs `fut`, failing with `io::ErrorKind::TimedOut` once `deadline` is reached.
+async fn with_deadline<T, F>(fut: F, deadline: Instant) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    let mut fut = pin!(fut);
+    let mut sleep = Box::pin(compio::time::sleep_until(deadline));
+    poll_fn(|cx| {
+        if let Poll::Ready(ret) = fut.as_mut().poll(cx) {
+            return Poll::Ready(ret);
+        }
+        // compio may complete a timer early, when a cancelled one had its slot before
+        while sleep.as_mut().poll(cx).is_ready() {
+            if Instant::now() >= deadline {
+                return Poll::Ready(Err(io::Error::new(ErrorKind::TimedOut, "timed out")));
+            }
+            sleep = Box::pin(compio::time::sleep_until(deadline));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Treats failures caused by the peer having closed the transport as success, when closing it ourselves.
+fn closed_by_peer<T>(ret: io::Result<T>) -> io::Result<()> {
+    match ret {
+        Ok(_) => Ok(()),
+        Err(e) => match e.kind() {
+            ErrorKind::NotConnected | ErrorKind::BrokenPipe | ErrorKind::ConnectionReset => Ok(()),
+            _ => Err(e),
+        },
     }
 }
 
 #[inline]
 fn ssl_err_into_io(err: openssl::ssl::Error) -> io::Error {
-    err.into_io_error().unwrap_or_else(|e| io::Error::new(io::ErrorKind::Other, e))
+    match Alert::from_error(&err) {
+        Some(alert) => io::Error::other(PeerAlertError { alert, error: err }),
+        None if CorruptRecordError::matches(&err) => {
+            io::Error::new(ErrorKind::InvalidData, CorruptRecordError(err))
+        }
+        // `ssl::Error` displays its error stack and also returns it as the source, so the stack is wrapped
+        // instead, keeping the OpenSSL message from appearing twice in error chains
+        None => match err.ssl_error() {
+            Some(stack) => io::Error::other(stack.clone()),
+            None => err.into_io_error().unwrap_or_else(io::Error::other),
+        },
+    }
 }
 
 impl<S: AsyncRead> AsyncRead for SslStream<S> {
+    /// Reads decrypted data into `buf`, waiting for the transport if none is available.
+    ///
+    /// Not cancel safe, see [cancel safety](SslStream#cancel-safety).
     async fn read<B: IoBufMut>(&mut self, mut buf: B) -> BufResult<usize, B> {
+        // The whole capacity, including the uninitialized tail. OpenSSL never reads from it, so there is no
+        // need to zero it beforehand; `set_buf_init` is the only place marking bytes as initialized.
         let read_buf = buf.as_mut_slice();
+        // nothing to read into, touching neither the session nor the transport
+        if read_buf.is_empty() {
+            return BufResult(Ok(0), buf);
+        }
+        if let Err(e) = self.check_interrupted() {
+            return BufResult(Err(e), buf);
+        }
+        self.pace_read().await;
+        let mut rounds = self.io_rounds();
         loop {
             let ret = self.stream.ssl_read_uninit(read_buf);
             match ret {
                 Ok(n) => {
                     // SAFETY: the length we just read
                     unsafe { buf.set_buf_init(n) };
+                    self.record_read(&buf.as_slice()[..n]);
                     return BufResult(Ok(n), buf);
                 }
                 Err(e) if e.code() == ErrorCode::ZERO_RETURN => {
-                    return BufResult(Ok(0), buf);
+                    return BufResult(self.zero_return(), buf);
                 }
                 Err(e) if e.code() == ErrorCode::WANT_READ => {
-                    match self.stream.get_mut().fill_read_buf().await {
+                    if let Err(e) = self.count_io_round(&mut rounds) {
+                        return BufResult(Err(e), buf);
+                    }
+                    match self.fill_transport().await {
                         Ok(_) => continue,
                         Err(e) => return BufResult(Err(e), buf),
                     }
                 }
-                Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => {}
-                Err(e) => return BufResult(Err(ssl_err_into_io(e)), buf),
+                // the transport reached EOF without close_notify
+                Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => {
+                    return BufResult(Ok(0), buf);
+                }
+                Err(e) => return BufResult(Err(self.ssl_err(e)), buf),
             }
         }
     }
@@ -188,65 +2431,43 @@ impl<S: AsyncRead> AsyncRead for SslStream<S> {
 
 /// `AsyncRead` is needed for shutting down stream.
 impl<S: AsyncWrite + AsyncRead> AsyncWrite for SslStream<S> {
+    /// Encrypts `buf` and sends it to the transport, unless held back by
+    /// [`set_write_buffer_threshold`](SslStream::set_write_buffer_threshold).
+    ///
+    /// Not cancel safe, see [cancel safety](SslStream#cancel-safety).
     async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
-        let slice = buf.as_slice();
-        loop {
-            let ret = self.stream.ssl_write(slice);
-            match ret {
-                Ok(n) => {
-                    let ret = self.stream.get_mut().flush_write_buf().await;
-                    return BufResult(ret.map(|_| n), buf);
-                }
-                Err(e) if e.code() == ErrorCode::WANT_WRITE => {
-                    match self.stream.get_mut().flush_write_buf().await {
-                        Ok(_) => continue,
-                        Err(e) => return BufResult(Err(e), buf),
-                    }
-                }
-                Err(e) => return BufResult(Err(ssl_err_into_io(e)), buf),
-            }
-        }
+        // `buf` is owned by us, so the slice stays stable across `SSL_write` retries
+        let ret = self.write_slice(buf.as_slice()).await;
+        BufResult(ret, buf)
     }
 
     // OpenSSL does not support vectored writes
 
+    /// Encrypts the plaintext held back and sends everything buffered to the transport.
+    ///
+    /// Not cancel safe, see [cancel safety](SslStream#cancel-safety).
     async fn flush(&mut self) -> io::Result<()> {
+        self.check_interrupted()?;
+        self.flush_plaintext().await?;
         loop {
             match self.stream.flush() {
                 Ok(_) => {
-                    self.stream.get_mut().flush_write_buf().await?;
+                    self.flush_transport().await?;
                     return Ok(());
                 }
                 Err(e) if e.kind() == ErrorKind::WouldBlock => {
-                    self.stream.get_mut().flush_write_buf().await?;
+                    self.flush_transport().await?;
                 }
                 e => return e,
             }
         }
     }
 
+    /// Sends `close_notify` and shuts down the underlying stream, without waiting for the peer's `close_notify`.
+    ///
+    /// See [`SslStream::shutdown_bidirectional`] to wait for the peer as well. Not cancel safe, see
+    /// [cancel safety](SslStream#cancel-safety).
     async fn shutdown(&mut self) -> io::Result<()> {
-        loop {
-            let ret = self.stream.shutdown();
-            match ret {
-                Ok(ShutdownResult::Sent) => {
-                    self.stream.get_mut().flush_write_buf().await?;
-                }
-                Ok(ShutdownResult::Received) => {
-                    break;
-                }
-                Err(e) if e.code() == ErrorCode::WANT_WRITE => {
-                    self.stream.get_mut().flush_write_buf().await?;
-                }
-                Err(e) if e.code() == ErrorCode::WANT_READ => {
-                    self.stream.get_mut().fill_read_buf().await?;
-                }
-                Err(e) if e.code() == ErrorCode::SYSCALL && e.io_error().is_none() => {
-                    break;
-                }
-                Err(e) => return Err(ssl_err_into_io(e)),
-            }
-        }
-        self.stream.get_mut().get_mut().shutdown().await
+        self.close_notify(false).await
     }
 }
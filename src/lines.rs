@@ -0,0 +1,135 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::{fmt, io, mem};
+
+use compio::buf::IntoInner;
+use compio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader};
+use futures_core::Stream;
+
+use crate::SslStream;
+
+/// A line being read through [`Stream`], owning the reader until it completes.
+type NextLine<S> = Pin<Box<dyn Future<Output = (LineReader<S>, io::Result<Option<String>>)>>>;
+
+/// The lines of the decrypted data of a stream, created by [`SslStream::lines`].
+///
+/// A line ends with `\n` or `\r\n`, neither of which is part of the returned line. The data after the
+/// last line ending, if any, is returned as the final line at end of file.
+///
+/// The lines are read with [`next_line`](Self::next_line), or as a [`Stream`] of `io::Result<String>`,
+/// e.g. with the combinators of `futures::StreamExt`.
+pub struct Lines<S> {
+    // `None` while `next` is pending, its future owns the reader then
+    reader: Option<LineReader<S>>,
+    next: Option<NextLine<S>>,
+}
+
+struct LineReader<S> {
+    reader: BufReader<SslStream<S>>,
+    line: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite> Lines<S> {
+    pub(crate) fn new(stream: SslStream<S>) -> Lines<S> {
+        Lines {
+            reader: Some(LineReader {
+                reader: BufReader::new(stream),
+                line: Vec::new(),
+            }),
+            next: None,
+        }
+    }
+
+    /// Reads the next line, returning `None` at end of file.
+    ///
+    /// A line that is not valid UTF-8 fails with [`ErrorKind::InvalidData`](io::ErrorKind::InvalidData)
+    /// and is skipped, so the following lines can still be read.
+    ///
+    /// # Cancel safety
+    ///
+    /// The future must not be dropped before it completes, the stream is unusable afterwards.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        // a line already being read through `Stream` comes first
+        if let Some(next) = &mut self.next {
+            let (reader, res) = next.await;
+            self.next = None;
+            self.reader = Some(reader);
+            return res;
+        }
+        self.reader.as_mut().expect("reader taken by a pending line").next_line().await
+    }
+
+    /// Consumes the `Lines`, returning the stream.
+    ///
+    /// Data read from the stream but not yet returned as a line is lost.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a line polled through [`Stream`] is still pending.
+    pub fn into_inner(self) -> SslStream<S> {
+        self.reader.expect("a line is still pending").reader.into_inner()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> LineReader<S> {
+    async fn next_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+            match available.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    self.line.extend_from_slice(&available[..end]);
+                    self.reader.consume(end + 1);
+                    if self.line.last() == Some(&b'\r') {
+                        self.line.pop();
+                    }
+                    break;
+                }
+                None => {
+                    let len = available.len();
+                    self.line.extend_from_slice(available);
+                    self.reader.consume(len);
+                }
+            }
+        }
+        String::from_utf8(mem::take(&mut self.line))
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + 'static> Stream for Lines<S> {
+    type Item = io::Result<String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let next = this.next.get_or_insert_with(|| {
+            let mut reader = this.reader.take().expect("reader taken by a pending line");
+            Box::pin(async move {
+                let res = reader.next_line().await;
+                (reader, res)
+            })
+        });
+        let (reader, res) = ready!(next.as_mut().poll(cx));
+        this.next = None;
+        this.reader = Some(reader);
+        Poll::Ready(res.transpose())
+    }
+}
+
+// nothing is pinned in place, the reader moves in and out of the boxed future
+impl<S> Unpin for Lines<S> {}
+
+impl<S: fmt::Debug> fmt::Debug for Lines<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lines")
+            .field("reader", &self.reader.as_ref().map(|r| &r.reader))
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,81 @@
+use std::io::{self, Cursor};
+
+use compio::BufResult;
+use compio::buf::{IoBuf, IoBufMut};
+use compio::io::{AsyncRead, AsyncReadAt, AsyncWrite, AsyncWriteAt};
+
+/// A transport reading from and writing to separate positioned stores, e.g. files or `Vec<u8>`s.
+///
+/// [`SslStream`](crate::SslStream) drives its transport sequentially through [`AsyncRead`] and [`AsyncWrite`].
+/// compio already implements both for a [`Cursor`] over an [`AsyncReadAt`] or [`AsyncWriteAt`], but a single
+/// cursor shares its position between reads and writes. This adapter keeps one cursor per direction, and
+/// reading past the end of the reader is an EOF.
+///
+/// This allows reproducing handshake issues deterministically without a peer: the recorded bytes of the
+/// peer are replayed from the reader, and everything sent ends up in the writer for inspection. Only the
+/// peer's first flight can be replayed meaningfully, as the following messages depend on the random values
+/// of both sides.
+#[derive(Debug)]
+pub struct PositionedStream<R, W> {
+    reader: Cursor<R>,
+    writer: Cursor<W>,
+}
+
+impl<R, W> PositionedStream<R, W> {
+    /// Creates a stream reading `reader` and writing `writer`, both from the start.
+    pub fn new(reader: R, writer: W) -> PositionedStream<R, W> {
+        PositionedStream {
+            reader: Cursor::new(reader),
+            writer: Cursor::new(writer),
+        }
+    }
+
+    /// Returns a shared reference to the store read from.
+    #[inline(always)]
+    pub fn reader(&self) -> &R {
+        self.reader.get_ref()
+    }
+
+    /// Returns a shared reference to the store written to.
+    #[inline(always)]
+    pub fn writer(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Returns the number of bytes read so far.
+    #[inline(always)]
+    pub fn read_position(&self) -> u64 {
+        self.reader.position()
+    }
+
+    /// Returns the number of bytes written so far.
+    #[inline(always)]
+    pub fn write_position(&self) -> u64 {
+        self.writer.position()
+    }
+
+    /// Consumes the stream, returning the reader and the writer.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader.into_inner(), self.writer.into_inner())
+    }
+}
+
+impl<R: AsyncReadAt, W> AsyncRead for PositionedStream<R, W> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        self.reader.read(buf).await
+    }
+}
+
+impl<R, W: AsyncWriteAt> AsyncWrite for PositionedStream<R, W> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        self.writer.write(buf).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.writer.shutdown().await
+    }
+}
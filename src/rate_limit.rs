@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+
+/// A token bucket pacing plaintext bytes to a rate, see [`SslStream::set_read_rate_limit`](crate::SslStream::set_read_rate_limit).
+///
+/// The bucket holds up to one second's worth of bytes and starts full. An operation waits until the bucket
+/// is not in debt, then takes all the bytes it moved, which may leave the bucket in debt for the next one.
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    pub(crate) fn new(bytes_per_sec: u64) -> RateLimit {
+        let rate = bytes_per_sec.max(1) as f64;
+        RateLimit {
+            rate,
+            tokens: rate,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * self.rate).min(self.rate);
+        self.last = now;
+    }
+
+    /// Waits until the bucket is out of debt.
+    pub(crate) async fn acquire(&mut self) {
+        self.refill();
+        // compio may complete a timer early, so check again after each
+        while self.tokens < 0.0 {
+            compio::time::sleep(Duration::from_secs_f64(-self.tokens / self.rate)).await;
+            self.refill();
+        }
+    }
+
+    /// Takes `n` bytes from the bucket.
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.tokens -= n as f64;
+    }
+}
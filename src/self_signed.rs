@@ -0,0 +1,41 @@
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{X509, X509Builder, X509NameBuilder};
+
+/// Builds a self-signed certificate for `cn` with a fresh P-256 key, valid for `days` from now.
+///
+/// `extend` appends the extensions, e.g. the subject alternative name, before the certificate is signed with
+/// SHA-256. The serial number is random.
+pub(crate) fn self_signed<F>(cn: &str, days: u32, extend: F) -> Result<(X509, PKey<Private>), ErrorStack>
+where
+    F: FnOnce(&mut X509Builder) -> Result<(), ErrorStack>,
+{
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, cn)?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(days)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    extend(&mut builder)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+    Ok((builder.build(), key))
+}
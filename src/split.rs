@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use compio::BufResult;
+use compio::buf::{IoBuf, IoBufMut};
+use compio::io::{AsyncRead, AsyncWrite};
+
+use crate::SslStream;
+
+/// The read half of an [`SslStream`], created by [`SslStream::split`].
+#[derive(Debug)]
+pub struct ReadHalf<S> {
+    inner: compio::io::ReadHalf<SslStream<S>>,
+    id: Arc<()>,
+}
+
+/// The write half of an [`SslStream`], created by [`SslStream::split`].
+#[derive(Debug)]
+pub struct WriteHalf<S> {
+    inner: compio::io::WriteHalf<SslStream<S>>,
+    id: Arc<()>,
+}
+
+pub(crate) fn split<S: AsyncRead + AsyncWrite>(stream: SslStream<S>) -> (ReadHalf<S>, WriteHalf<S>) {
+    let (read_half, write_half) = compio::io::split(stream);
+    let id = Arc::new(());
+    let read_half = ReadHalf {
+        inner: read_half,
+        id: id.clone(),
+    };
+    (
+        read_half,
+        WriteHalf {
+            inner: write_half,
+            id,
+        },
+    )
+}
+
+pub(crate) fn reunite<S: Unpin>(
+    read_half: ReadHalf<S>,
+    write_half: WriteHalf<S>,
+) -> Result<SslStream<S>, ReuniteError<S>> {
+    if Arc::ptr_eq(&read_half.id, &write_half.id) {
+        Ok(read_half.inner.unsplit(write_half.inner))
+    } else {
+        Err(ReuniteError(read_half, write_half))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for ReadHalf<S> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        self.inner.read(buf).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for WriteHalf<S> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        self.inner.write(buf).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+/// An error returned by [`SslStream::reunite`] when the halves come from different streams.
+///
+/// Both halves are given back unchanged.
+pub struct ReuniteError<S>(pub ReadHalf<S>, pub WriteHalf<S>);
+
+impl<S> fmt::Debug for ReuniteError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").field(&"..").field(&"..").finish()
+    }
+}
+
+impl<S> fmt::Display for ReuniteError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("tried to reunite halves that are not from the same stream")
+    }
+}
+
+impl<S> Error for ReuniteError<S> {}
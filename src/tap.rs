@@ -0,0 +1,88 @@
+use std::fmt;
+use std::io;
+use std::mem::MaybeUninit;
+
+use compio::BufResult;
+use compio::buf::{IoBuf, IoBufMut};
+use compio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// The direction of the bytes passed to a [`Tap`] callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapDirection {
+    /// Received from the peer.
+    Read,
+    /// Sent to the peer.
+    Write,
+}
+
+type TapFn = dyn FnMut(TapDirection, &mut [u8]);
+
+/// A transport passing every chunk of bytes read from and written to `S` to a callback.
+///
+/// Used as the transport of an [`SslStream`](crate::SslStream), the callback sees the encrypted TLS records,
+/// including the handshake, never the plaintext. It may modify the bytes, e.g. flip one to test the handling
+/// of corrupted records. Every byte is passed exactly once, in order, so the chunks of each direction
+/// concatenate to the wire traffic.
+pub struct Tap<S> {
+    inner: S,
+    tap: Box<TapFn>,
+}
+
+impl<S> Tap<S> {
+    /// Wraps `inner`, passing its traffic to `tap`.
+    pub fn new(inner: S, tap: impl FnMut(TapDirection, &mut [u8]) + 'static) -> Tap<S> {
+        Tap {
+            inner,
+            tap: Box::new(tap),
+        }
+    }
+
+    /// Returns a shared reference to the wrapped transport.
+    #[inline(always)]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes the `Tap`, returning the wrapped transport.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Tap<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tap").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Tap<S> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        let start = buf.buf_len();
+        let BufResult(ret, mut buf) = self.inner.read(buf).await;
+        if let Ok(n) = ret {
+            let read = &mut buf.as_mut_slice()[start..start + n];
+            // SAFETY: the transport initialized the `n` bytes it read
+            let read = unsafe { &mut *(read as *mut [MaybeUninit<u8>] as *mut [u8]) };
+            (self.tap)(TapDirection::Read, read);
+        }
+        BufResult(ret, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Tap<S> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        let mut chunk = buf.as_slice().to_vec();
+        (self.tap)(TapDirection::Write, &mut chunk);
+        // written in full, so that no byte is passed twice on a retry
+        let BufResult(ret, _) = self.inner.write_all(chunk).await;
+        BufResult(ret.map(|()| buf.buf_len()), buf)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
@@ -1,26 +1,346 @@
-use std::net::Ipv4Addr;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
 use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use compio::io::{AsyncReadExt, AsyncWrite};
+use compio::BufResult;
+use compio::buf::{IntoInner, IoBuf, IoBufMut, SetBufInit};
+use compio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use compio::net::{TcpListener, TcpStream};
-use openssl::ssl::{Ssl, SslAcceptor, SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+    ShutdownState, SslAcceptor, SslAcceptorBuilder, SslConnector, SslConnectorBuilder, SslContext,
+    SslContextBuilder, SslFiletype, SslMethod, SslMode, SslOptions, SslSessionCacheMode, SslVerifyMode,
+    SslVersion,
+};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509, X509Builder, X509NameBuilder};
 
-use super::SslStream;
+use super::{
+    ACME_TLS_ALPN, Alert, AsyncTransport, BoxedSslStream, ConnectError, HandshakeError, HandshakeStep,
+    HandshakeWant, PositionedStream, ResumptionKind, ReuniteError, ShutdownOutcome, SslStream, TlsAcceptor,
+    TlsConnector, acceptor_from_pem, acme_tls_alpn_cert, check_version, connector_from_ca_pem,
+    enable_session_cache, ex_data, ffi, is_fips, set_acme_alpn_select, set_alpn_select, set_psk_client,
+    set_psk_server, set_session_timeout,
+};
+#[cfg(ossl111)]
+use super::{
+    CertResponse, ClientHelloInfo, client_hello_ciphers, client_hello_extensions, client_hello_versions,
+    enable_client_hello_peek, pause_cert_lookup, pause_handshake, set_cert_callback,
+    set_early_data_replay_check,
+};
+#[cfg(ossl300)]
+use super::{pause_verification, set_cert_verify_callback};
+
+/// A transport counting the reads and writes issued to it.
+struct CountingStream<S> {
+    inner: S,
+    reads: Rc<Cell<usize>>,
+    writes: Rc<Cell<usize>>,
+}
+
+impl<S: AsyncRead> AsyncRead for CountingStream<S> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.read(buf).await
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for CountingStream<S> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        self.writes.set(self.writes.get() + 1);
+        self.inner.write(buf).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+/// A transport reading a single byte at a time, counting the reads.
+struct TricklingStream<S> {
+    inner: S,
+    reads: Rc<Cell<usize>>,
+}
+
+impl<S: AsyncRead> AsyncRead for TricklingStream<S> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        self.reads.set(self.reads.get() + 1);
+        let len = buf.buf_len();
+        self.inner.read(buf.slice(len..len + 1)).await.into_inner()
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for TricklingStream<S> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        self.inner.write(buf).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+/// A page of memory aligned for direct I/O.
+#[repr(C, align(4096))]
+struct Page([u8; 4096]);
+
+/// An owned buffer backed by an aligned [`Page`], like the ones used for `O_DIRECT` files.
+struct AlignedBuf {
+    page: Box<Page>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new() -> AlignedBuf {
+        AlignedBuf {
+            page: Box::new(Page([0; 4096])),
+            len: 0,
+        }
+    }
+
+    fn from_slice(data: &[u8]) -> AlignedBuf {
+        let mut buf = AlignedBuf::new();
+        buf.page.0[..data.len()].copy_from_slice(data);
+        buf.len = data.len();
+        buf
+    }
+}
+
+unsafe impl IoBuf for AlignedBuf {
+    fn as_buf_ptr(&self) -> *const u8 {
+        self.page.0.as_ptr()
+    }
+
+    fn buf_len(&self) -> usize {
+        self.len
+    }
+
+    fn buf_capacity(&self) -> usize {
+        self.page.0.len()
+    }
+}
+
+impl SetBufInit for AlignedBuf {
+    unsafe fn set_buf_init(&mut self, len: usize) {
+        self.len = self.len.max(len);
+    }
+}
+
+unsafe impl IoBufMut for AlignedBuf {
+    fn as_buf_mut_ptr(&mut self) -> *mut u8 {
+        self.page.0.as_mut_ptr()
+    }
+}
+
+/// A transport flipping a bit in the last byte of the first read, like a faulty middlebox.
+struct CorruptingStream<S> {
+    inner: S,
+    corrupted: bool,
+}
+
+impl<S: AsyncRead> AsyncRead for CorruptingStream<S> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        let BufResult(ret, mut buf) = self.inner.read(buf).await;
+        if let Ok(n) = ret
+            && n > 0
+            && !self.corrupted
+        {
+            self.corrupted = true;
+            let last = buf.buf_len() - 1;
+            let byte = &mut buf.as_mut_slice()[last];
+            // SAFETY: the byte was just read
+            *byte = std::mem::MaybeUninit::new(unsafe { byte.assume_init() } ^ 1);
+        }
+        BufResult(ret, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for CorruptingStream<S> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        self.inner.write(buf).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+/// A transport recording everything written to it.
+struct RecordingStream<S> {
+    inner: S,
+    written: Rc<RefCell<Vec<u8>>>,
+}
+
+impl<S: AsyncRead> AsyncRead for RecordingStream<S> {
+    async fn read<B: IoBufMut>(&mut self, buf: B) -> BufResult<usize, B> {
+        self.inner.read(buf).await
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for RecordingStream<S> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        let BufResult(ret, buf) = self.inner.write(buf).await;
+        if let Ok(n) = ret {
+            self.written.borrow_mut().extend_from_slice(&buf.as_slice()[..n]);
+        }
+        BufResult(ret, buf)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.inner.shutdown().await
+    }
+}
+
+/// Builds a certificate chain for `localhost`, from the root to the leaf, with `intermediates` CAs in between.
+fn cert_chain(intermediates: usize) -> Vec<(X509, PKey<Private>)> {
+    let mut chain: Vec<(X509, PKey<Private>)> = Vec::new();
+    for level in 0..intermediates + 2 {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let is_leaf = level == intermediates + 1;
+        let mut name = X509NameBuilder::new().unwrap();
+        let cn = if is_leaf {
+            "localhost".to_owned()
+        } else {
+            format!("CA {level}")
+        };
+        name.append_entry_by_nid(Nid::COMMONNAME, &cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(level as u32 + 1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        if is_leaf {
+            let san = SubjectAlternativeName::new()
+                .dns("localhost")
+                .build(&builder.x509v3_context(None, None))
+                .unwrap();
+            builder.append_extension(san).unwrap();
+        } else {
+            builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+        }
+        let (issuer_name, issuer_key) = match chain.last() {
+            Some((issuer, issuer_key)) => (issuer.subject_name().to_owned().unwrap(), issuer_key),
+            None => (name, &key),
+        };
+        builder.set_issuer_name(&issuer_name).unwrap();
+        builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+        chain.push((cert, key));
+    }
+    chain
+}
 
 const TEST_PAYLOAD: &[u8] = include_bytes!("../README.md");
 
-#[compio::test]
-async fn self_test() {
-    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 10443)).await.unwrap();
-    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+/// Binds a listener to a free port on the loopback interface, returning it with its address.
+async fn test_listener() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+/// Sets the self-signed certificate in `test/`, issued for `localhost`, and its key.
+fn set_test_cert(builder: &mut SslContextBuilder) {
     builder.set_certificate_chain_file("./test/public.pem").unwrap();
     builder.set_private_key_file("./test/privkey.pem", SslFiletype::PEM).unwrap();
-    let tls_acceptor = builder.build();
+}
+
+/// An acceptor builder following Mozilla's modern recommendations, with the test certificate.
+fn test_acceptor_builder() -> SslAcceptorBuilder {
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    builder
+}
+
+fn test_acceptor() -> TlsAcceptor {
+    TlsAcceptor::new(test_acceptor_builder().build())
+}
+
+/// A connector builder accepting any server certificate.
+fn test_connector_builder() -> SslConnectorBuilder {
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    builder
+}
+
+fn test_connector() -> TlsConnector {
+    TlsConnector::new(test_connector_builder().build())
+}
+
+/// Connects a client to a server with [`test_connector`] and [`test_acceptor`], returning both streams, in
+/// that order, after the handshake.
+async fn tls_pair() -> (SslStream<TcpStream>, SslStream<TcpStream>) {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        tls_acceptor.accept(stream).await.unwrap()
+    });
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let client = test_connector().connect("localhost", stream).await.unwrap();
+    (client, server_task.await.unwrap())
+}
+
+/// Walks the source chain of `err`, asserting no level repeats a message of a level above it, as error
+/// reporters print every level.
+fn assert_distinct_chain(err: &dyn Error) {
+    let mut messages = vec![err.to_string()];
+    let mut source = err.source();
+    while let Some(err) = source {
+        let message = err.to_string();
+        assert!(
+            messages.iter().all(|m| !m.contains(&message)),
+            "{message:?} repeated in {messages:?}"
+        );
+        messages.push(message);
+        source = err.source();
+    }
+}
+
+#[compio::test]
+async fn self_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
 
     let server_task = compio::runtime::spawn(async move {
         let (stream, _) = listener.accept().await.unwrap();
-        let mut stream = SslStream::new(Ssl::new(tls_acceptor.context()).unwrap(), stream).unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
         stream.accept().await.unwrap();
         let buf = Vec::with_capacity(TEST_PAYLOAD.len());
         let (_, buf) = stream.read_to_end(buf).await.unwrap();
@@ -28,10 +348,8 @@ async fn self_test() {
     });
 
     // client
-    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
-    builder.set_verify(SslVerifyMode::NONE);
-    let tls_connector = builder.build();
-    let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, 10443)).await.unwrap();
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
     let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
     let mut stream = SslStream::new(tls_ctx, stream).unwrap();
     stream.connect().await.unwrap();
@@ -93,10 +411,3724 @@ async fn server_test() {
         .unwrap();
 
     let (stream, _) = listener.accept().await.unwrap();
-    let mut stream = SslStream::new(Ssl::new(tls_acceptor.context()).unwrap(), stream).unwrap();
+    let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
     stream.accept().await.unwrap();
     let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
 
     child.kill().unwrap();
     assert_eq!(buf, TEST_PAYLOAD);
 }
+
+#[compio::test]
+async fn large_write_test() {
+    // larger than a single TLS record and the internal write buffer
+    let payload: Vec<u8> = (0..128 * 1024).map(|i| i as u8).collect();
+    let expected = payload.clone();
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, expected);
+    });
+
+    // client without partial write nor moving write buffer, so `SSL_write` must be retried
+    // with the same buffer until the whole payload is written
+    let mut builder = SslContext::builder(SslMethod::tls_client()).unwrap();
+    builder.set_verify(SslVerifyMode::NONE);
+    let mode = builder.set_mode(SslMode::empty());
+    assert!(!mode.contains(SslMode::ENABLE_PARTIAL_WRITE));
+    assert!(!mode.contains(SslMode::ACCEPT_MOVING_WRITE_BUFFER));
+    let ctx = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = SslStream::from_context(&ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    let (n, _) = stream.write(payload).await.unwrap();
+    assert_eq!(n, 128 * 1024);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn alpn_select_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    set_alpn_select(&mut builder, |offered| {
+        [b"h2".as_slice(), b"http/1.1"].into_iter().find_map(|p| offered.iter().copied().find(|&o| o == p))
+    });
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        assert_eq!(stream.selected_alpn_protocol(), Some(b"h2".as_slice()));
+        assert_eq!(stream.selected_alpn_protocol_str(), Some("h2"));
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut builder = test_connector_builder();
+    builder.set_alpn_protos(b"\x08http/1.1\x02h2").unwrap();
+    let tls_connector = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert_eq!(stream.selected_alpn_protocol(), Some(b"h2".as_slice()));
+    assert_eq!(stream.selected_alpn_protocol_str(), Some("h2"));
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn ssl_mut_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    // the connector verifies peers by default, which fails on our self-signed certificate
+    let tls_connector = SslConnector::builder(SslMethod::tls_client()).unwrap().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.ssl_mut().set_verify(SslVerifyMode::NONE);
+    stream.connect().await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn read_uninit_test() {
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        server.write(TEST_PAYLOAD).await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+
+    // nothing initialized, the data should land in the spare capacity
+    let buf = Vec::with_capacity(TEST_PAYLOAD.len());
+    let (n, buf) = stream.read(buf).await.unwrap();
+    assert!(n > 0);
+    assert_eq!(buf.len(), n);
+    assert_eq!(buf.capacity(), TEST_PAYLOAD.len());
+    assert_eq!(buf, TEST_PAYLOAD[..n]);
+    let (_, buf) = stream.read_to_end(buf).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn max_cert_list_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    builder.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+    let mut tls_acceptor = TlsAcceptor::new(builder.build());
+    // far smaller than our test certificate
+    tls_acceptor.set_max_cert_list(64);
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let err = tls_acceptor.accept(stream).await.err().unwrap();
+        assert!(err.error().to_string().contains("excessive message size"));
+    });
+
+    let mut builder = test_connector_builder();
+    set_test_cert(&mut builder);
+    let tls_connector = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    // TLS 1.3 clients may finish before the server has seen the certificate
+    if stream.connect().await.is_ok() {
+        // the server aborted, so no data could be received
+        let (res, buf) = stream.read_to_end(Vec::new()).await.into();
+        assert!(res.is_err() || buf.is_empty());
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn hostname_mismatch_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = tls_acceptor.accept(stream).await {
+                stream.shutdown().await.unwrap();
+            }
+        }
+    });
+
+    // trust our self-signed certificate, issued for `localhost`
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("./test/public.pem").unwrap();
+    let tls_connector = TlsConnector::new(builder.build());
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    match tls_connector.connect("example.com", stream).await {
+        Err(ConnectError::HostnameMismatch { expected, cert_names }) => {
+            assert_eq!(expected, "example.com");
+            assert_eq!(cert_names, ["localhost"]);
+        }
+        _ => panic!("expected hostname mismatch"),
+    }
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn write_buffer_threshold_test() {
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+    });
+
+    stream.set_write_buffer_threshold(512);
+    for chunk in TEST_PAYLOAD.chunks(7) {
+        let (n, _) = stream.write(chunk).await.unwrap();
+        assert_eq!(n, chunk.len());
+    }
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(any(ossl111, libressl340))]
+#[compio::test]
+async fn ciphersuites_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.set_ciphersuites("TLS_CHACHA20_POLY1305_SHA256").unwrap();
+    assert!(stream.current_cipher().is_none());
+    stream.connect().await.unwrap();
+    assert_eq!(
+        stream.current_cipher().unwrap().standard_name(),
+        Some("TLS_CHACHA20_POLY1305_SHA256")
+    );
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn accept_with_cancel_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    // a client never starting the handshake
+    let _client = TcpStream::connect(addr).await.unwrap();
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+    let err = stream.accept_with_cancel(compio::time::sleep(Duration::from_millis(100))).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Interrupted);
+}
+
+#[cfg(ossl300)]
+#[compio::test]
+async fn negotiated_params_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        assert!(!stream.negotiated_group().unwrap().is_empty());
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert!(stream.peer_signature_type().is_none());
+    stream.connect().await.unwrap();
+    // TLS 1.3 only allows PSS for RSA keys
+    assert_eq!(stream.peer_signature_type(), Some(Nid::RSASSAPSS));
+    assert!(!stream.negotiated_group().unwrap().is_empty());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn handshake_reads_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let reads = Rc::new(Cell::new(0));
+        let stream = CountingStream {
+            inner: stream,
+            reads: reads.clone(),
+            writes: Rc::default(),
+        };
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        // ClientHello, then the client's Finished
+        assert!(reads.get() <= 3);
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let reads = Rc::new(Cell::new(0));
+    let stream = CountingStream {
+        inner: stream,
+        reads: reads.clone(),
+        writes: Rc::default(),
+    };
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    // the whole server flight, no read per partial record
+    assert!(reads.get() <= 2);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl300)]
+#[compio::test]
+async fn send_session_ticket_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    // no ticket unless explicitly issued
+    builder.set_num_tickets(0).unwrap();
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        stream.send_session_ticket().await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        assert!(stream.ssl().session_reused());
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    // receive the ticket
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert!(buf.is_empty());
+    let session = stream.ssl().session().unwrap().to_owned();
+    stream.shutdown().await.unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session).unwrap() };
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert!(stream.ssl().session_reused());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn pause_handshake_test() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use openssl::ssl::ClientHelloResponse;
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    builder.set_client_hello_callback(move |ssl, _| {
+        if counter.fetch_add(1, Ordering::SeqCst) > 0 {
+            return Ok(ClientHelloResponse::SUCCESS);
+        }
+        // resolved by some other thread later on
+        let (tx, rx) = futures_channel::oneshot::channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            tx.send(()).unwrap();
+        });
+        Ok(pause_handshake(ssl, async move { rx.await.unwrap() }))
+    });
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "test-util")]
+#[compio::test]
+async fn generate_self_signed_test() {
+    use super::generate_self_signed;
+
+    let (cert, key) = generate_self_signed("localhost");
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+    builder.set_certificate(&cert).unwrap();
+    builder.set_private_key(&key).unwrap();
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        stream.write(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    // trusted as is, with hostname verification
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.cert_store_mut().add_cert(cert.clone()).unwrap();
+    let tls_connector = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    assert_eq!(
+        stream.ssl().peer_certificate().unwrap().to_der().unwrap(),
+        cert.to_der().unwrap()
+    );
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+
+    // fresh serial for every certificate
+    let (other, _) = generate_self_signed("127.0.0.1");
+    assert_ne!(
+        other.serial_number().to_bn().unwrap(),
+        cert.serial_number().to_bn().unwrap()
+    );
+}
+
+#[compio::test]
+async fn write_then_read_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let (_, req) = stream.read_exact(Vec::with_capacity(4)).await.unwrap();
+        assert_eq!(req, b"ping");
+        stream.write(b"pong").await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let mut resp = [0; 16];
+    let (written, read) = stream.write_then_read(b"ping", &mut resp).await.unwrap();
+    assert_eq!(written, 4);
+    assert_eq!(&resp[..read], b"pong");
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn peer_alert_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(tls_acceptor.accept(stream).await.is_err());
+    });
+
+    // no client certificate
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert!(stream.last_alert().is_none());
+    // TLS 1.3 clients finish before the server has checked the certificate
+    let err = match stream.connect().await {
+        Ok(()) => stream.read_to_end(Vec::new()).await.0.unwrap_err(),
+        Err(e) => e,
+    };
+    assert_eq!(err.to_string(), "peer alert: certificate_required");
+    assert_distinct_chain(&err);
+    let alert = stream.last_alert().unwrap();
+    assert_eq!(alert.code(), 116);
+    assert_eq!(alert.description(), "certificate_required");
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn sliced_write_test() {
+    let payload: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = payload.clone();
+
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, expected);
+    });
+
+    // the connector enables partial writes, so the caller tracks the offset
+    let mut buf = payload;
+    let mut offset = 0;
+    while offset < buf.len() {
+        let BufResult(n, slice) = stream.write(buf.slice(offset..)).await;
+        assert_eq!(slice.begin(), offset);
+        offset += n.unwrap();
+        buf = slice.into_inner();
+    }
+    assert_eq!(buf.len(), 256 * 1024);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn handshake_error_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        match tls_acceptor.accept(stream).await {
+            Err(HandshakeError::Failure(stream, _)) => {
+                assert_eq!(stream.last_alert().unwrap().description(), "unknown_ca")
+            }
+            _ => panic!("expected handshake failure"),
+        }
+    });
+
+    // our self-signed certificate is not trusted
+    let tls_connector = TlsConnector::new(SslConnector::builder(SslMethod::tls_client()).unwrap().build());
+    let stream = TcpStream::connect(addr).await.unwrap();
+    match tls_connector.connect("localhost", stream).await {
+        Err(err @ ConnectError::Handshake(HandshakeError::Failure(..))) => {
+            // the chain walks through every level
+            assert_eq!(err.to_string(), "failed to connect over TLS");
+            let source = err.source().unwrap();
+            assert!(source.is::<HandshakeError<TcpStream>>());
+            assert_eq!(source.to_string(), "TLS handshake failed");
+            assert!(source.source().unwrap().is::<io::Error>());
+            assert_distinct_chain(&err);
+            let ConnectError::Handshake(HandshakeError::Failure(stream, _)) = err else {
+                unreachable!()
+            };
+            assert_eq!(
+                stream.ssl().verify_result().error_string(),
+                "self-signed certificate"
+            )
+        }
+        _ => panic!("expected handshake failure"),
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn session_cache_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    builder.set_options(SslOptions::NO_TICKET);
+    enable_session_cache(&mut builder, b"session_cache_test", 16).unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for reused in [false, true] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            assert_eq!(stream.ssl().session_reused(), reused);
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    // a TLS 1.2 client without ticket support
+    let mut builder = test_connector_builder();
+    builder.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    builder.set_options(SslOptions::NO_TICKET);
+    let tls_connector = builder.build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    let session = stream.ssl().session().unwrap().to_owned();
+    stream.shutdown().await.unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session).unwrap() };
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert!(stream.ssl().session_reused());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn shutdown_test() {
+    let (mut stream, mut server) = tls_pair().await;
+    let (closed_tx, closed_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        // do not reply before the client is done
+        closed_rx.await.unwrap();
+        server.shutdown_bidirectional().await.unwrap();
+        assert_eq!(
+            server.get_shutdown(),
+            ShutdownState::SENT | ShutdownState::RECEIVED
+        );
+    });
+
+    compio::time::timeout(Duration::from_secs(5), stream.shutdown()).await.unwrap().unwrap();
+    assert_eq!(stream.get_shutdown(), ShutdownState::SENT);
+    closed_tx.send(()).unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn read_at_offset_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let mut message = 100u32.to_be_bytes().to_vec();
+        message.extend_from_slice(&TEST_PAYLOAD[..100]);
+        stream.write_all(message).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let (_, mut buf) = stream.read_exact(Vec::with_capacity(4)).await.unwrap();
+    let len = u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize;
+    buf.reserve_exact(len);
+    while buf.len() < 4 + len {
+        let offset = buf.len();
+        let (n, read) = stream.read_at_offset(buf, offset).await.unwrap();
+        assert!(n > 0);
+        assert_eq!(read.len(), offset + n);
+        buf = read;
+    }
+    assert_eq!(buf[4..], TEST_PAYLOAD[..100]);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn shutdown_eof_test() {
+    let (mut stream, mut server) = tls_pair().await;
+    let (closed_tx, closed_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        // FIN without close_notify, keeping the socket open
+        server.get_mut().shutdown().await.unwrap();
+        closed_rx.await.unwrap();
+    });
+
+    compio::time::timeout(Duration::from_secs(5), stream.shutdown_bidirectional())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stream.get_shutdown(), ShutdownState::SENT);
+    closed_tx.send(()).unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn ex_data_test() {
+    #[derive(Debug, PartialEq)]
+    struct ConnId(u64);
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    let seen = Arc::new(AtomicU64::new(0));
+    let seen_cb = seen.clone();
+    builder.set_servername_callback(move |ssl, _| {
+        seen_cb.store(ex_data::<ConnId>(ssl).unwrap().0, Ordering::SeqCst);
+        Ok(())
+    });
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        assert!(stream.ex_data::<ConnId>().is_none());
+        stream.set_ex_data(ConnId(7));
+        stream.accept().await.unwrap();
+        assert_eq!(stream.ex_data::<ConnId>(), Some(&ConnId(7)));
+        assert!(stream.ex_data::<u64>().is_none());
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+    assert_eq!(seen.load(Ordering::SeqCst), 7);
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn max_fragment_length_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let server_written = written.clone();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let stream = RecordingStream {
+            inner: stream,
+            written: server_written,
+        };
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        assert_eq!(stream.max_fragment_length(), Some(512));
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert_eq!(
+        stream.set_max_fragment_length(8192).unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+    stream.set_max_fragment_length(512).unwrap();
+    assert_eq!(
+        stream.set_record_size_limit(512).unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+    stream.connect().await.unwrap();
+    assert_eq!(stream.max_fragment_length(), Some(512));
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+
+    // walk the records the server sent
+    let written = written.borrow();
+    let mut records = 0;
+    let mut rest = &written[..];
+    while rest.len() >= 5 {
+        let len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        assert!(len <= 512 + 256, "record of {len} bytes");
+        rest = &rest[5 + len..];
+        records += 1;
+    }
+    assert!(rest.is_empty());
+    assert!(records > TEST_PAYLOAD.len() / 512);
+}
+
+#[compio::test]
+async fn release_buffers_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.set_release_buffers(true);
+        loop {
+            let (n, buf) = stream.read(Vec::with_capacity(1024)).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            stream.write_all(buf).await.unwrap();
+        }
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    stream.set_release_buffers(true);
+    for (i, chunk) in TEST_PAYLOAD.chunks(100).take(10).enumerate() {
+        // idle in between, so the buffers are released
+        compio::time::sleep(Duration::from_millis(10)).await;
+        stream.set_release_buffers(i % 2 == 0);
+        stream.write_all(chunk).await.unwrap();
+        let (_, buf) = stream.read_exact(Vec::with_capacity(chunk.len())).await.unwrap();
+        assert_eq!(buf, chunk);
+    }
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(unix)]
+#[compio::test]
+async fn from_raw_fd_test() {
+    use std::os::fd::IntoRawFd;
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    // a socket connected outside of compio
+    let fd = std::net::TcpStream::connect(addr).unwrap().into_raw_fd();
+    let tls_connector = test_connector_builder().build();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = unsafe { SslStream::from_raw_fd(tls_ctx, fd) }.unwrap();
+    stream.connect().await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn read_at_least_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // one record per chunk, arriving separately
+        for chunk in TEST_PAYLOAD[..50].chunks(10) {
+            stream.write_all(chunk).await.unwrap();
+            compio::time::sleep(Duration::from_millis(10)).await;
+        }
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let mut buf = [0; 64];
+    let n = stream.read_at_least(&mut buf, 30).await.unwrap();
+    assert!((30..50).contains(&n));
+    // only EOF cuts it short
+    let rest = stream.read_at_least(&mut buf[n..], 64).await.unwrap();
+    assert_eq!(n + rest, 50);
+    assert_eq!(buf[..50], TEST_PAYLOAD[..50]);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn disable_tickets_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+            stream.disable_tickets().unwrap();
+            stream.accept().await.unwrap();
+            assert!(!stream.ssl().session_reused());
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    // TLS 1.3 tickets arrive after the handshake
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert!(buf.is_empty());
+    let session = stream.ssl().session().unwrap().to_owned();
+    stream.shutdown().await.unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session).unwrap() };
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert!(!stream.ssl().session_reused());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn byte_counters_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let (_, buf) = stream.read_exact(Vec::with_capacity(100)).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        assert_eq!(stream.bytes_read(), buf.len() as u64);
+        assert_eq!(stream.bytes_written(), TEST_PAYLOAD.len() as u64);
+        stream.shutdown().await.unwrap();
+        (stream.raw_bytes_read(), stream.raw_bytes_written())
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    assert_eq!((stream.bytes_read(), stream.bytes_written()), (0, 0));
+    stream.write_all(&TEST_PAYLOAD[..100]).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    assert_eq!(stream.bytes_written(), 100);
+    assert_eq!(stream.bytes_read(), TEST_PAYLOAD.len() as u64);
+    stream.shutdown().await.unwrap();
+    let (server_raw_read, server_raw_written) = server_task.await.unwrap();
+
+    // both directions carry the handshake and record overhead on top
+    assert!(stream.raw_bytes_written() > 100);
+    assert!(stream.raw_bytes_read() > TEST_PAYLOAD.len() as u64);
+    assert_eq!(stream.raw_bytes_read(), server_raw_written);
+    assert!(server_raw_read <= stream.raw_bytes_written());
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn client_hello_fingerprint_test() {
+    use std::sync::Mutex;
+
+    use openssl::ssl::ClientHelloResponse;
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    let offered = Arc::new(Mutex::new(None));
+    let captured = offered.clone();
+    builder.set_client_hello_callback(move |ssl, _| {
+        *captured.lock().unwrap() = Some((
+            client_hello_ciphers(ssl).unwrap(),
+            client_hello_versions(ssl).unwrap(),
+            client_hello_extensions(ssl).unwrap(),
+        ));
+        Ok(ClientHelloResponse::SUCCESS)
+    });
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // only available within the callback
+        assert!(client_hello_ciphers(stream.ssl()).is_none());
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut builder = test_connector_builder();
+    builder.set_min_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    builder.set_cipher_list("ECDHE-RSA-AES128-GCM-SHA256").unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    let tls_connector = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+
+    let (ciphers, versions, extensions) = offered.lock().unwrap().take().unwrap();
+    assert_eq!(ciphers[..2], [0x1301, 0xc02f]);
+    assert_eq!(versions, [0x0304, 0x0303]);
+    // server_name and supported_versions
+    assert_eq!(extensions[0], 0);
+    assert!(extensions.contains(&43));
+}
+
+#[compio::test]
+async fn proto_version_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        assert_eq!(stream.ssl().version_str(), "TLSv1.2");
+        assert_eq!(stream.protocol_version_number(), 0x0303);
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.set_min_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    stream.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    stream.connect().await.unwrap();
+    assert_eq!(stream.ssl().version_str(), "TLSv1.2");
+    assert_eq!(stream.protocol_version_number(), 0x0303);
+    assert!(!stream.is_tlsv13());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn shutdown_after_eof_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let reads = Rc::new(Cell::new(0));
+    let stream = CountingStream {
+        inner: stream,
+        reads: reads.clone(),
+        writes: Rc::default(),
+    };
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    assert!(stream.get_shutdown().contains(ShutdownState::RECEIVED));
+    let before = reads.get();
+    stream.shutdown_bidirectional().await.unwrap();
+    assert_eq!(reads.get(), before);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn sendfile_test() {
+    let content: Vec<u8> = TEST_PAYLOAD.iter().copied().cycle().take(100 * 1024).collect();
+    let path = std::env::temp_dir().join("compio-openssl-sendfile-test");
+    std::fs::write(&path, &content).unwrap();
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let file = compio::fs::File::open(&path).await.unwrap();
+        let sent = stream.ssl_sendfile(&file, 10, 50 * 1024).await.unwrap();
+        assert_eq!(sent, 50 * 1024);
+        // cut short by the end of the file
+        let sent = stream.ssl_sendfile(&file, 90 * 1024, 20 * 1024).await.unwrap();
+        assert_eq!(sent, 10 * 1024);
+        stream.shutdown().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf[..50 * 1024], content[10..10 + 50 * 1024]);
+    assert_eq!(buf[50 * 1024..], content[90 * 1024..]);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn cert_compression_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // no client certificate at all
+        assert_eq!(stream.cert_compression_algorithm(), None);
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let alg = stream.cert_compression_algorithm();
+    // depends on the algorithms OpenSSL was built with
+    #[cfg(ossl320)]
+    assert!(matches!(alg, None | Some("zlib" | "brotli" | "zstd")));
+    #[cfg(not(ossl320))]
+    assert_eq!(alg, None);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn verify_depth_test() {
+    // root, three intermediates and the leaf
+    let chain = cert_chain(3);
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+    let (leaf, leaf_key) = chain.last().unwrap();
+    builder.set_certificate(leaf).unwrap();
+    builder.set_private_key(leaf_key).unwrap();
+    for (cert, _) in chain[1..chain.len() - 1].iter().rev() {
+        builder.add_extra_chain_cert(cert.clone()).unwrap();
+    }
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = tls_acceptor.accept(stream).await {
+                // a TLS 1.3 client may reject the chain after the server is done
+                let _ = stream.read_to_end(Vec::new()).await;
+                let _ = stream.shutdown().await;
+            }
+        }
+    });
+
+    let mut store = X509StoreBuilder::new().unwrap();
+    store.add_cert(chain[0].0.clone()).unwrap();
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_verify_cert_store(store.build()).unwrap();
+    let tls_connector = builder.build();
+
+    for (depth, ok) in [(2, false), (3, true)] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.set_verify_depth(depth);
+        assert_eq!(stream.connect().await.is_ok(), ok, "depth {depth}");
+        if ok {
+            stream.shutdown().await.unwrap();
+        } else {
+            assert_eq!(
+                stream.ssl().verify_result().as_raw(),
+                ffi::X509_V_ERR_CERT_CHAIN_TOO_LONG
+            );
+        }
+    }
+    server_task.await.unwrap();
+}
+
+#[cfg(unix)]
+#[compio::test]
+async fn boxed_transport_test() {
+    use compio::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join("compio-openssl-boxed-test.sock");
+    let _ = std::fs::remove_file(&path);
+    let (tcp_listener, addr) = test_listener().await;
+    let unix_listener = UnixListener::bind(&path).await.unwrap();
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (tcp, _) = tcp_listener.accept().await.unwrap();
+        let (unix, _) = unix_listener.accept().await.unwrap();
+        let mut streams: Vec<BoxedSslStream> = Vec::new();
+        for transport in [tcp.boxed(), unix.boxed()] {
+            streams.push(tls_acceptor.accept(transport).await.unwrap());
+        }
+        for stream in &mut streams {
+            stream.write_all(TEST_PAYLOAD).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let tls_connector = test_connector();
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let unix = UnixStream::connect(&path).await.unwrap();
+    let mut streams: Vec<BoxedSslStream> = Vec::new();
+    for transport in [tcp.boxed(), unix.boxed()] {
+        streams.push(tls_connector.connect("localhost", transport).await.unwrap());
+    }
+    for stream in &mut streams {
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+        stream.shutdown().await.unwrap();
+    }
+    server_task.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[compio::test]
+async fn unix_socket_test() {
+    use compio::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join("compio-openssl-unix-test.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).await.unwrap();
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        // echo
+        let (_, buf) = stream.read_exact(Vec::with_capacity(TEST_PAYLOAD.len())).await.unwrap();
+        stream.write_all(buf).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = UnixStream::connect(&path).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    stream.write_all(TEST_PAYLOAD).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(ossl110)]
+#[compio::test]
+async fn handshake_wants_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+    let (cancelled_tx, cancelled_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        // not answering the first client before it gave up
+        let (_stream, _) = listener.accept().await.unwrap();
+        cancelled_rx.await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        assert_eq!(stream.handshake_wants(), Some(HandshakeWant::WantRead));
+        stream.accept().await.unwrap();
+        assert_eq!(stream.handshake_wants(), None);
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert_eq!(stream.handshake_wants(), Some(HandshakeWant::WantWrite));
+    let err = stream.connect_with_cancel(compio::time::sleep(Duration::from_millis(100))).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Interrupted);
+    // the hello is sent, waiting for the server
+    assert_eq!(stream.handshake_wants(), Some(HandshakeWant::WantRead));
+    drop(stream);
+    cancelled_tx.send(()).unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert_eq!(stream.handshake_wants(), None);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn early_data_rejected_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    builder.set_max_early_data(16384).unwrap();
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        // the early data is rejected, as it is not read before the handshake
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        assert!(stream.ssl().session_reused());
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    // receiving the session tickets
+    stream.read_to_end(Vec::new()).await.unwrap();
+    let session = stream.ssl().session().unwrap().to_owned();
+    assert_eq!(session.max_early_data(), 16384);
+    stream.shutdown().await.unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session).unwrap() };
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert!(!stream.connect_with_early_data(TEST_PAYLOAD).await.unwrap());
+    assert!(!stream.early_data_accepted());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn flush_on_write_test() {
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+        server.shutdown().await.unwrap();
+    });
+
+    assert!(stream.is_tlsv13());
+    assert_eq!(stream.pending_write_bytes(), 0);
+
+    stream.set_flush_on_write(false);
+    let raw_written = stream.raw_bytes_written();
+    stream.write_all(&TEST_PAYLOAD[..100]).await.unwrap();
+    // the payload, a record header and the AEAD tag
+    let pending = stream.pending_write_bytes();
+    assert!(pending > 100);
+    assert_eq!(pending as u64, stream.raw_bytes_written() - raw_written);
+    stream.flush().await.unwrap();
+    assert_eq!(stream.pending_write_bytes(), 0);
+
+    // nothing pending after a round trip through OpenSSL's stream either
+    let mut stream = SslStream::from(stream.into_openssl());
+    assert_eq!(stream.pending_write_bytes(), 0);
+
+    stream.set_flush_on_write(true);
+    stream.write_all(&TEST_PAYLOAD[100..]).await.unwrap();
+    assert_eq!(stream.pending_write_bytes(), 0);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn replay_test() {
+    // recording the client hello, the client then sees an EOF
+    let tls_ctx = test_connector_builder().build().configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, PositionedStream::new(Vec::new(), Vec::new())).unwrap();
+    stream.connect().await.unwrap_err();
+    let client_hello = stream.get_ref().writer().clone();
+    assert_eq!(client_hello[0], 0x16);
+
+    let tls_acceptor = test_acceptor_builder().build();
+
+    // the server answers the replayed hello, then sees an EOF
+    for _ in 0..2 {
+        let transport = PositionedStream::new(client_hello.as_slice(), Vec::new());
+        let mut stream = SslStream::from_context(tls_acceptor.context(), transport).unwrap();
+        stream.accept().await.unwrap_err();
+        assert_eq!(stream.ssl().version_str(), "TLSv1.3");
+        assert_eq!(stream.get_ref().read_position(), client_hello.len() as u64);
+        let server_hello = stream.get_ref().writer();
+        assert_eq!(&server_hello[..3], &[0x16, 0x03, 0x03]);
+    }
+}
+
+#[compio::test]
+async fn verify_errors_test() {
+    // root, an intermediate and the leaf
+    let chain = cert_chain(1);
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+    builder.set_certificate(&chain[2].0).unwrap();
+    builder.set_private_key(&chain[2].1).unwrap();
+    builder.add_extra_chain_cert(chain[1].0.clone()).unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = tls_acceptor.accept(stream).await {
+                let _ = stream.read_to_end(Vec::new()).await;
+                let _ = stream.shutdown().await;
+            }
+        }
+    });
+
+    let mut store = X509StoreBuilder::new().unwrap();
+    store.add_cert(chain[0].0.clone()).unwrap();
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_verify_cert_store(store.build()).unwrap();
+    let trusting = builder.build();
+    let untrusting = SslConnector::builder(SslMethod::tls_client()).unwrap().build();
+
+    let cases = [
+        (&trusting, "localhost", vec![(2, 0), (1, 0), (0, 0)]),
+        (
+            &trusting,
+            "example.com",
+            vec![(0, ffi::X509_V_ERR_HOSTNAME_MISMATCH)],
+        ),
+        (
+            &untrusting,
+            "localhost",
+            vec![(1, ffi::X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY)],
+        ),
+    ];
+    for (tls_connector, domain, expected) in cases {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut tls_ctx = tls_connector.configure().unwrap().into_ssl(domain).unwrap();
+        tls_ctx.param_mut().set_host(domain).unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.record_verify_errors();
+        let ok = stream.connect().await.is_ok();
+        let errors: Vec<_> = stream.verify_errors().into_iter().map(|(d, e)| (d, e.as_raw())).collect();
+        assert_eq!(errors, expected, "{domain}");
+        assert_eq!(ok, expected.iter().all(|(_, e)| *e == 0));
+        if ok {
+            stream.shutdown().await.unwrap();
+        }
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn read_to_end_timeout_test() {
+    let (mut stream, mut server) = tls_pair().await;
+    let (done_tx, done_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        // trickling for longer than the idle timeout, then stalling
+        for chunk in TEST_PAYLOAD.chunks(TEST_PAYLOAD.len() / 5 + 1) {
+            compio::time::sleep(Duration::from_millis(60)).await;
+            server.write_all(chunk.to_vec()).await.unwrap();
+        }
+        done_rx.await.unwrap();
+    });
+
+    let (ret, buf) = stream.read_to_end_timeout(b"prefix".to_vec(), Duration::from_millis(200)).await;
+    assert_eq!(ret.unwrap_err().kind(), ErrorKind::TimedOut);
+    assert_eq!(&buf[..6], b"prefix");
+    assert_eq!(&buf[6..], TEST_PAYLOAD);
+    drop(stream);
+    done_tx.send(()).unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn options_test() {
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.clear_options(SslOptions::NO_RENEGOTIATION);
+    let tls_connector = builder.build();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, PositionedStream::new(Vec::new(), Vec::new())).unwrap();
+    assert!(!stream.options().contains(SslOptions::NO_RENEGOTIATION));
+
+    let opts = stream.set_options(SslOptions::NO_RENEGOTIATION | SslOptions::NO_TICKET);
+    assert!(opts.contains(SslOptions::NO_RENEGOTIATION | SslOptions::NO_TICKET));
+    assert_eq!(stream.options(), opts);
+    let opts = stream.clear_options(SslOptions::NO_TICKET);
+    assert!(opts.contains(SslOptions::NO_RENEGOTIATION));
+    assert!(!opts.contains(SslOptions::NO_TICKET));
+    assert_eq!(stream.options(), opts);
+
+    // the context is left alone
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let stream = SslStream::new(tls_ctx, PositionedStream::new(Vec::new(), Vec::new())).unwrap();
+    assert!(!stream.options().contains(SslOptions::NO_RENEGOTIATION));
+}
+
+#[compio::test]
+async fn small_writes_test() {
+    let (mut stream, mut server) = tls_pair().await;
+    let payload: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    let expected = payload.clone();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, expected);
+        server.shutdown().await.unwrap();
+    });
+
+    // without flushing each write, the transport buffer fills up and `SSL_write` wants to write
+    stream.set_flush_on_write(false);
+    let mut max_pending = 0;
+    for chunk in payload.chunks(100) {
+        stream.write_all(chunk.to_vec()).await.unwrap();
+        max_pending = max_pending.max(stream.pending_write_bytes());
+    }
+    assert!(max_pending < payload.len());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+/// Measures 100-byte writes through [`SslStream::write`] against `SSL_write` followed by flushing the transport,
+/// the least any write has to do, printing the time per call of each.
+///
+/// Run with `cargo test --release small_write_overhead_test -- --ignored --nocapture`.
+#[compio::test]
+#[ignore = "timing measurement"]
+async fn small_write_overhead_test() {
+    const ROUNDS: usize = 10;
+    const WRITES: usize = 10_000;
+    let chunk = &TEST_PAYLOAD[..100];
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf.len(), 2 * ROUNDS * WRITES * chunk.len());
+    });
+
+    // alternating, so both see the same conditions over time
+    let (mut raw, mut wrapped) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        for _ in 0..WRITES {
+            assert_eq!(stream.stream.ssl_write(chunk).unwrap(), chunk.len());
+            stream.stream.get_mut().flush_write_buf().await.unwrap();
+        }
+        raw += start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..WRITES {
+            let (n, _) = stream.write(chunk).await.unwrap();
+            assert_eq!(n, chunk.len());
+        }
+        wrapped += start.elapsed();
+    }
+    let calls = (ROUNDS * WRITES) as u32;
+    println!(
+        "SSL_write and flush: {:?} per call, SslStream::write: {:?} per call",
+        raw / calls,
+        wrapped / calls
+    );
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn writable_test() {
+    let (mut stream, mut server) = tls_pair().await;
+    let (read_tx, read_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_exact(vec![0; 100]).await.unwrap();
+        assert_eq!(buf, &TEST_PAYLOAD[..100]);
+        read_tx.send(()).unwrap();
+        let (_, buf) = server.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, &TEST_PAYLOAD[100..]);
+        server.shutdown().await.unwrap();
+    });
+
+    stream.writable().await.unwrap();
+
+    stream.set_flush_on_write(false);
+    stream.write_all(&TEST_PAYLOAD[..100]).await.unwrap();
+    assert!(stream.pending_write_bytes() > 0);
+    stream.writable().await.unwrap();
+    assert_eq!(stream.pending_write_bytes(), 0);
+    // the peer got the data without a flush
+    read_rx.await.unwrap();
+
+    stream.write_all(&TEST_PAYLOAD[100..]).await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn split_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let stream = tls_acceptor.accept(stream).await.unwrap();
+        let (mut read_half, mut write_half) = stream.split();
+        write_half.write_all(TEST_PAYLOAD).await.unwrap();
+        write_half.shutdown().await.unwrap();
+        let (_, buf) = read_half.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+        let mut stream = SslStream::reunite(read_half, write_half).unwrap();
+        assert!(stream.get_shutdown().contains(ShutdownState::RECEIVED));
+    });
+
+    let tls_connector = test_connector_builder().build();
+    // a borrowed transport
+    let mut tcp_stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, &mut tcp_stream).unwrap();
+    stream.connect().await.unwrap();
+
+    let (mut read_half, mut write_half) = stream.split_borrowed();
+    let read = async {
+        let mut buf = Vec::new();
+        loop {
+            let (n, chunk) = read_half.read(Vec::with_capacity(1024)).await.unwrap();
+            if n == 0 {
+                break buf;
+            }
+            buf.extend_from_slice(&chunk);
+        }
+    };
+    let write = async {
+        write_half.write_all(TEST_PAYLOAD).await.unwrap();
+        write_half.shutdown().await.unwrap();
+    };
+    let (buf, ()) = futures_util::join!(read, write);
+    assert_eq!(buf, TEST_PAYLOAD);
+    drop(stream);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn reunite_test() {
+    let tls_connector = SslConnector::builder(SslMethod::tls_client()).unwrap().build();
+    let new_stream = || {
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        SslStream::new(tls_ctx, PositionedStream::new(Vec::new(), Vec::new())).unwrap()
+    };
+    let (read_a, write_a) = new_stream().split();
+    let (read_b, write_b) = new_stream().split();
+
+    let ReuniteError(read_a, write_b) = SslStream::reunite(read_a, write_b).unwrap_err();
+    let ReuniteError(read_b, write_a) = SslStream::reunite(read_b, write_a).unwrap_err();
+    SslStream::reunite(read_a, write_a).unwrap();
+    SslStream::reunite(read_b, write_b).unwrap();
+}
+
+#[compio::test]
+async fn shutdown_timeout_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+    let (done_tx, done_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        // replying to the close_notify
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert!(buf.is_empty());
+        stream.shutdown().await.unwrap();
+
+        // staying silent
+        let (stream, _) = listener.accept().await.unwrap();
+        let _stream = tls_acceptor.accept(stream).await.unwrap();
+        done_rx.await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    for expected in [ShutdownOutcome::Clean, ShutdownOutcome::SentOnly] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.connect().await.unwrap();
+        let start = Instant::now();
+        let outcome = stream.shutdown_timeout(Duration::from_millis(200)).await.unwrap();
+        assert_eq!(outcome, expected);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+    done_tx.send(()).unwrap();
+    server_task.await.unwrap();
+}
+
+/// Splits TLS records, returning their content types.
+#[cfg(feature = "tap")]
+fn record_types(mut data: &[u8]) -> Vec<u8> {
+    let mut types = Vec::new();
+    while !data.is_empty() {
+        let len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        types.push(data[0]);
+        data = &data[5 + len..];
+    }
+    types
+}
+
+#[cfg(feature = "tap")]
+#[compio::test]
+async fn tap_test() {
+    use super::{Tap, TapDirection};
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        // the client rejects the corrupted handshake
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(tls_acceptor.accept(stream).await.is_err());
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    // recording
+    let read = Rc::new(RefCell::new(Vec::new()));
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let (r, w) = (read.clone(), written.clone());
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = Tap::new(stream, move |direction, bytes| match direction {
+        TapDirection::Read => r.borrow_mut().extend_from_slice(bytes),
+        TapDirection::Write => w.borrow_mut().extend_from_slice(bytes),
+    });
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+
+    let (read, written) = (read.take(), written.take());
+    assert_eq!(read.len() as u64, stream.raw_bytes_read());
+    assert_eq!(written.len() as u64, stream.raw_bytes_written());
+    // handshake records first, the ClientHello and ServerHello in the clear
+    assert_eq!(record_types(&written)[0], 0x16);
+    assert_eq!(written[5], 1);
+    assert_eq!(record_types(&read)[0], 0x16);
+    assert_eq!(read[5], 2);
+    // the plaintext never passes the tap
+    assert!(!read.windows(32).any(|w| w == &TEST_PAYLOAD[..32]));
+
+    // corrupting the first chunk received, which ends within an encrypted record
+    let corrupted = Rc::new(Cell::new(false));
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = Tap::new(stream, move |direction, bytes| {
+        if direction == TapDirection::Read && !bytes.is_empty() && !corrupted.replace(true) {
+            *bytes.last_mut().unwrap() ^= 1;
+        }
+    });
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    let result = async {
+        stream.connect().await?;
+        stream.read_to_end(Vec::new()).await.0
+    }
+    .await;
+    assert!(result.is_err());
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn oneshot_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+    let request = b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nOK";
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let (_, buf) = stream.read_exact(vec![0; request.len()]).await.unwrap();
+        assert_eq!(buf, request);
+        stream.write_all(response).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert!(buf.is_empty());
+        assert!(stream.get_shutdown().contains(ShutdownState::RECEIVED));
+    });
+
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("./test/public.pem").unwrap();
+    let tls_connector = TlsConnector::new(builder.build());
+    let buf = tls_connector.oneshot("localhost", addr, request).await.unwrap();
+    assert_eq!(buf, response);
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn num_tickets_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        for n in [0, 3, 2] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+            stream.set_num_tickets(n).unwrap();
+            stream.accept().await.unwrap();
+            assert!(!stream.ssl().session_reused());
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    // counting the tickets received
+    let tickets = Arc::new(AtomicU64::new(0));
+    let counter = tickets.clone();
+    let mut builder = test_connector_builder();
+    builder.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+    builder.set_new_session_callback(move |_, _| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    let tls_connector = builder.build();
+
+    let mut session = None;
+    for expected in [0, 3] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.connect().await.unwrap();
+        // TLS 1.3 tickets arrive after the handshake
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(tickets.swap(0, Ordering::Relaxed), expected);
+        session.get_or_insert_with(|| stream.ssl().session().unwrap().to_owned());
+        stream.shutdown().await.unwrap();
+    }
+
+    // the session of the first connection came without a ticket
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session.unwrap()).unwrap() };
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert!(!stream.ssl().session_reused());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn empty_read_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+    let (read_tx, read_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // nothing is sent until the client tried its empty read
+        read_rx.await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let reads = Rc::new(Cell::new(0));
+    let stream = CountingStream {
+        inner: stream,
+        reads: reads.clone(),
+        writes: Rc::default(),
+    };
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let before = reads.get();
+    let (n, buf) = stream.read(Vec::new()).await.unwrap();
+    assert_eq!(n, 0);
+    assert!(buf.is_empty());
+    assert_eq!(reads.get(), before);
+    read_tx.send(()).unwrap();
+
+    // not mistaken for EOF
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn negotiated_group_name_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for expected in ["x25519", "secp256r1", "secp384r1"] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            assert_eq!(stream.negotiated_group_name().as_deref(), Some(expected));
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    for groups in ["X25519", "P-256", "P-384"] {
+        let mut builder = test_connector_builder();
+        builder.set_groups_list(groups).unwrap();
+        let tls_connector = builder.build();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        assert!(stream.negotiated_group_name().is_none());
+        stream.connect().await.unwrap();
+        assert!(stream.is_tlsv13());
+        assert!(!stream.negotiated_group_name().unwrap().is_empty());
+        stream.shutdown().await.unwrap();
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn connect_and_write_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            let (_, buf) = stream.read_exact(vec![0; 100]).await.unwrap();
+            assert_eq!(buf, &TEST_PAYLOAD[..100]);
+            stream.write_all(buf).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let mut counts = Vec::new();
+    for combined in [false, true] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reads, writes) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let stream = CountingStream {
+            inner: stream,
+            reads: reads.clone(),
+            writes: writes.clone(),
+        };
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        if combined {
+            assert_eq!(stream.connect_and_write(&TEST_PAYLOAD[..100]).await.unwrap(), 100);
+        } else {
+            stream.connect().await.unwrap();
+            stream.write_all(&TEST_PAYLOAD[..100]).await.unwrap();
+        }
+        assert_eq!(stream.pending_write_bytes(), 0);
+        counts.push((reads.get(), writes.get()));
+
+        let (_, buf) = stream.read_exact(vec![0; 100]).await.unwrap();
+        assert_eq!(buf, &TEST_PAYLOAD[..100]);
+        stream.shutdown().await.unwrap();
+    }
+    // TLS 1.3: the client Finished and the request share a write, with the same reads
+    assert_eq!(counts[1].0, counts[0].0);
+    assert_eq!(counts[1].1, counts[0].1 - 1);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn borrowed_stream_test() {
+    /// Generic code taking any reader by value.
+    async fn read_request<R: AsyncRead>(mut reader: R) -> Vec<u8> {
+        let (_, buf) = reader.read_exact(vec![0; 100]).await.unwrap();
+        buf
+    }
+
+    /// Generic code taking any writer by value.
+    async fn write_response<W: AsyncWrite>(mut writer: W, data: Vec<u8>) {
+        writer.write_all(data).await.unwrap();
+        writer.flush().await.unwrap();
+    }
+
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        let request = read_request(&mut server).await;
+        write_response(&mut server, request).await;
+        // still usable afterwards
+        server.shutdown().await.unwrap();
+    });
+
+    write_response(&mut stream, TEST_PAYLOAD[..100].to_vec()).await;
+    assert_eq!(read_request(&mut stream).await, &TEST_PAYLOAD[..100]);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn session_timeout_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    set_session_timeout(&mut builder, Duration::from_secs(600));
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        assert_eq!(stream.session_timeout(), Some(Duration::from_secs(600)));
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert!(stream.session_timeout().is_none());
+    assert!(stream.session_remaining().is_none());
+    stream.connect().await.unwrap();
+    // TLS 1.3 tickets arrive after the handshake
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert!(buf.is_empty());
+
+    assert_eq!(stream.session_timeout(), Some(Duration::from_secs(600)));
+    let created = stream.session_created().unwrap();
+    assert!(created.elapsed().unwrap_or_default() < Duration::from_secs(10));
+    let remaining = stream.session_remaining().unwrap();
+    assert!(remaining > Duration::from_secs(590) && remaining <= Duration::from_secs(600));
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn send_alert_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        // turned away before the handshake
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.send_alert(Alert::new(49)).await.unwrap();
+
+        // too late once the handshake encrypted records
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        let err = stream.send_alert(Alert::new(80)).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap_err();
+    assert_eq!(stream.last_alert().map(Alert::description), Some("access_denied"));
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert!(buf.is_empty());
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[test]
+fn fips_test() {
+    // the default configuration
+    assert!(!is_fips());
+
+    // switching the process over would affect the other tests, so use a separate library context instead
+    #[cfg(ossl300)]
+    {
+        use foreign_types::ForeignType;
+        use openssl::lib_ctx::LibCtx;
+        use openssl::provider::Provider;
+
+        use super::fips_enabled;
+
+        let ctx = LibCtx::new().unwrap();
+        assert!(!fips_enabled(ctx.as_ptr()));
+        let Ok(_provider) = Provider::try_load(Some(&ctx), "fips", true) else {
+            // the FIPS module is not installed
+            return;
+        };
+        assert!(!fips_enabled(ctx.as_ptr()));
+        assert_eq!(
+            unsafe { ffi::EVP_default_properties_enable_fips(ctx.as_ptr(), 1) },
+            1
+        );
+        assert!(fips_enabled(ctx.as_ptr()));
+    }
+}
+
+#[compio::test]
+async fn max_io_rounds_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        // the ClientHello alone takes hundreds of reads
+        let (stream, _) = listener.accept().await.unwrap();
+        let reads = Rc::new(Cell::new(0));
+        let stream = TricklingStream {
+            inner: stream,
+            reads: reads.clone(),
+        };
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.set_max_io_rounds(Some(100));
+        let err = stream.accept().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(reads.get(), 100);
+        drop(stream);
+
+        // the ClientHello fits, and completing it starts the count over
+        let (stream, _) = listener.accept().await.unwrap();
+        reads.set(0);
+        let stream = TricklingStream {
+            inner: stream,
+            reads: reads.clone(),
+        };
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.set_max_io_rounds(Some(550));
+        stream.accept().await.unwrap();
+        assert!(reads.get() > 550);
+        let (_, buf) = stream.read_exact(vec![0; 100]).await.unwrap();
+        assert_eq!(buf, &TEST_PAYLOAD[..100]);
+
+        // the record of 100 bytes, plus its header and tag, byte by byte
+        stream.set_max_io_rounds(Some(50));
+        let err = stream.read(Vec::with_capacity(100)).await.0.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap_err();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    stream.write_all(&TEST_PAYLOAD[..100]).await.unwrap();
+    stream.write_all(&TEST_PAYLOAD[100..200]).await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn acme_tls_alpn_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    let (challenge_cert, challenge_key) = acme_tls_alpn_cert("example.com", "token.thumbprint").unwrap();
+    let cert = challenge_cert.clone();
+    set_acme_alpn_select(
+        &mut builder,
+        move |domain| (domain == "example.com").then(|| (cert.clone(), challenge_key.clone())),
+        |offered| offered.iter().copied().find(|p| *p == b"h2"),
+    );
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+        // no challenge for this name
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(tls_acceptor.accept(stream).await.is_err());
+    });
+
+    let connect = async |domain: &str, alpn: &[u8]| {
+        let mut builder = test_connector_builder();
+        builder.set_alpn_protos(alpn).unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let tls_ctx = builder.build().configure().unwrap().into_ssl(domain).unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.connect().await.map(|()| stream)
+    };
+
+    // the validation server gets the challenge certificate
+    let mut stream = connect("example.com", b"\x0aacme-tls/1").await.unwrap();
+    assert_eq!(stream.ssl().selected_alpn_protocol(), Some(ACME_TLS_ALPN));
+    let peer = stream.ssl().peer_certificate().unwrap();
+    assert_eq!(peer.to_der().unwrap(), challenge_cert.to_der().unwrap());
+    let digest = openssl::hash::hash(MessageDigest::sha256(), b"token.thumbprint").unwrap();
+    let authorization = [&[0x04, 0x20][..], &digest].concat();
+    assert!(peer.to_der().unwrap().windows(34).any(|w| w == authorization));
+    stream.shutdown().await.unwrap();
+
+    // everyone else the regular one
+    let mut stream = connect("example.com", b"\x02h2\x08http/1.1").await.unwrap();
+    assert_eq!(stream.selected_alpn_protocol_str(), Some("h2"));
+    let peer = stream.ssl().peer_certificate().unwrap();
+    assert_ne!(peer.to_der().unwrap(), challenge_cert.to_der().unwrap());
+    stream.shutdown().await.unwrap();
+
+    assert!(connect("other.org", b"\x0aacme-tls/1").await.is_err());
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn psk_identity_test() {
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslContext::builder(SslMethod::tls_server()).unwrap();
+    builder.set_cipher_list("PSK-AES128-GCM-SHA256").unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    set_psk_server(&mut builder, |identity| {
+        (identity == b"device-1").then(|| KEY.to_vec())
+    });
+    let tls_context = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        for version in [SslVersion::TLS1_3, SslVersion::TLS1_2] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = SslStream::from_context(&tls_context, stream).unwrap();
+            stream.accept().await.unwrap();
+            assert_eq!(stream.ssl().version2(), Some(version));
+            assert_eq!(stream.psk_identity(), Some(&b"device-1"[..]));
+            stream.shutdown().await.unwrap();
+        }
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(&tls_context, stream).unwrap();
+        stream.accept().await.unwrap_err();
+    });
+
+    for (version, identity) in [
+        (SslVersion::TLS1_3, "device-1"),
+        (SslVersion::TLS1_2, "device-1"),
+        (SslVersion::TLS1_3, "device-2"),
+    ] {
+        let mut builder = SslContext::builder(SslMethod::tls_client()).unwrap();
+        builder.set_max_proto_version(Some(version)).unwrap();
+        builder.set_cipher_list("PSK-AES128-GCM-SHA256").unwrap();
+        builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+        set_psk_client(&mut builder, identity.as_bytes(), KEY);
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = SslStream::from_context(&builder.build(), stream).unwrap();
+        assert!(stream.psk_identity().is_none());
+        if identity == "device-2" {
+            stream.connect().await.unwrap_err();
+            break;
+        }
+        stream.connect().await.unwrap();
+        assert_eq!(stream.psk_identity(), Some(identity.as_bytes()));
+        assert_eq!(stream.resumption_kind(), ResumptionKind::Psk);
+        // no certificate involved
+        assert!(stream.ssl().peer_certificate().is_none());
+        stream.shutdown().await.unwrap();
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn lines_test() {
+    use futures_util::StreamExt;
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // a line split across records
+        stream.write_all(&b"first\r\nsec"[..]).await.unwrap();
+        stream.flush().await.unwrap();
+        stream.write_all(&b"ond\r\n\r\nbad \xff\nlast"[..]).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let mut lines = stream.lines();
+    assert_eq!(lines.next().await.unwrap().unwrap(), "first");
+    // the inherent method reads from the same lines
+    assert_eq!(lines.next_line().await.unwrap().as_deref(), Some("second"));
+    let rest: Vec<io::Result<String>> = (&mut lines).collect().await;
+    let [empty, bad, last] = &rest[..] else {
+        panic!("unexpected lines: {rest:?}")
+    };
+    assert_eq!(empty.as_ref().unwrap(), "");
+    assert_eq!(bad.as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    // the final line has no line ending
+    assert_eq!(last.as_ref().unwrap(), "last");
+    assert!(lines.next().await.is_none());
+    assert!(lines.next_line().await.unwrap().is_none());
+    lines.into_inner().shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn close_notify_eof_error_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // half of the announced content, then a clean close
+        stream.write_all(&TEST_PAYLOAD[..TEST_PAYLOAD.len() / 2]).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    stream.treat_close_notify_as_eof_error(true);
+    let BufResult(ret, buf) = stream.read_to_end(Vec::new()).await;
+    assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(buf, &TEST_PAYLOAD[..TEST_PAYLOAD.len() / 2]);
+
+    // the close is accepted once no more content is expected
+    stream.treat_close_notify_as_eof_error(false);
+    let (n, _) = stream.read(vec![0; 16]).await.unwrap();
+    assert_eq!(n, 0);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn acceptor_session_timeout_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    set_session_timeout(&mut builder, Duration::from_secs(1));
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for reused in [false, true, false] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            assert_eq!(stream.ssl().session_reused(), reused);
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    // TLS 1.2, where the ticket is part of the handshake
+    let mut builder = test_connector_builder();
+    builder.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    let tls_connector = builder.build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert_eq!(stream.session_timeout(), Some(Duration::from_secs(1)));
+    let session = stream.ssl().session().unwrap().to_owned();
+    stream.shutdown().await.unwrap();
+
+    for delay in [Duration::ZERO, Duration::from_millis(2100)] {
+        compio::time::sleep(delay).await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        unsafe { tls_ctx.set_session(&session).unwrap() };
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.connect().await.unwrap();
+        assert_eq!(stream.ssl().session_reused(), delay.is_zero());
+        stream.shutdown().await.unwrap();
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn readable_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        compio::time::sleep(Duration::from_millis(50)).await;
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    stream.readable().await.unwrap();
+    // readiness consumed nothing
+    assert_eq!(stream.bytes_read(), 0);
+    let (n, _) = stream.read(vec![0; 4]).await.unwrap();
+    assert_eq!(n, 4);
+
+    // the rest of the record is already decrypted
+    let raw = stream.raw_bytes_read();
+    stream.readable().await.unwrap();
+    assert_eq!(stream.raw_bytes_read(), raw);
+
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, &TEST_PAYLOAD[4..]);
+    // EOF is readable too
+    stream.readable().await.unwrap();
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn early_data_replay_test() {
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    builder.set_max_early_data(16384).unwrap();
+    // `SSL_OP_NO_ANTI_REPLAY`, relying on the replay cache alone as a deployment of several servers would
+    builder.set_options(SslOptions::from_bits_retain(1 << 24));
+    let seen = Arc::new(Mutex::new(HashSet::new()));
+    set_early_data_replay_check(&mut builder, move |random| {
+        seen.lock().unwrap().insert(random.to_vec())
+    });
+    let tls_acceptor = builder.build();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        stream.accept().await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut early = Vec::new();
+        let mut buf = [0; 4096];
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        loop {
+            let n = stream.read_realy_data(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            early.extend_from_slice(&buf[..n]);
+        }
+        stream.accept().await.unwrap();
+        assert!(stream.early_data_accepted());
+        assert_eq!(early, TEST_PAYLOAD);
+        stream.shutdown().await.unwrap();
+
+        // the replayed early data is skipped, and the handshake fails without the client's keys
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(tls_acceptor.context(), stream).unwrap();
+        assert_eq!(stream.read_realy_data(&mut buf).await.unwrap(), 0);
+        assert!(!stream.early_data_accepted());
+        stream.accept().await.unwrap_err();
+        assert_eq!(stream.bytes_read(), 0);
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    // receiving the session tickets
+    stream.read_to_end(Vec::new()).await.unwrap();
+    let session = stream.ssl().session().unwrap().to_owned();
+    stream.shutdown().await.unwrap();
+
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = RecordingStream {
+        inner: stream,
+        written: written.clone(),
+    };
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session).unwrap() };
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert!(stream.connect_with_early_data(TEST_PAYLOAD).await.unwrap());
+    stream.shutdown().await.unwrap();
+
+    // an attacker sending the recorded connection again
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(written.take()).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let _ = stream.read_to_end(Vec::new()).await;
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn try_handshake_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut ssl = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    ssl.set_connect_state();
+    let mut stream = SslStream::new(ssl, stream).unwrap();
+
+    let mut steps = Vec::new();
+    loop {
+        let step = stream.try_handshake().unwrap();
+        steps.push(step);
+        match step {
+            HandshakeStep::Done => break,
+            HandshakeStep::WantRead => assert_ne!(stream.fill_read_buf().await.unwrap(), 0),
+            HandshakeStep::WantWrite => assert_ne!(stream.flush_write_buf().await.unwrap(), 0),
+        }
+    }
+    // the ClientHello, then the server's flight, then the client's Finished
+    assert_eq!(steps[..2], [HandshakeStep::WantWrite, HandshakeStep::WantRead]);
+    assert_eq!(
+        steps[steps.len() - 2..],
+        [HandshakeStep::WantWrite, HandshakeStep::Done]
+    );
+    assert_eq!(stream.handshake_wants(), None);
+    // stepping a complete handshake is a no-op
+    assert_eq!(stream.try_handshake().unwrap(), HandshakeStep::Done);
+
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn connect_host_test() {
+    use std::net::Ipv6Addr;
+
+    use super::connector::{happy_eyeballs, interleave};
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.write_all(TEST_PAYLOAD).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let v4 = addr;
+    let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, addr.port()));
+    let other_v4 = SocketAddr::from((Ipv4Addr::LOCALHOST, addr.port() + 1));
+    assert_eq!(interleave(vec![v6, v6, v4, other_v4]), [v6, v4, v6, other_v4]);
+    assert_eq!(interleave(vec![v4, other_v4, v6]), [v4, v6, other_v4]);
+
+    let tls_connector = test_connector();
+    let mut stream = tls_connector.connect_host("localhost", addr.port()).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+
+    // nothing listens on IPv6, the refused attempt hands over without waiting for the attempt delay
+    let start = Instant::now();
+    let stream = happy_eyeballs(vec![v6, v4]).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(250));
+    assert_eq!(stream.peer_addr().unwrap(), v4);
+    let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+
+    let err = happy_eyeballs(vec![v6]).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    let err = happy_eyeballs(Vec::new()).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[compio::test]
+async fn master_key_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+    let (key_tx, key_rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let mut key = [0; 64];
+        let n = stream.master_key(&mut key);
+        key_tx.send(key[..n].to_vec()).unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut builder = test_connector_builder();
+    builder.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    let tls_connector = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert_eq!(stream.master_key(&mut [0; 48]), 0);
+    stream.connect().await.unwrap();
+
+    let mut key = [0; 64];
+    let n = stream.master_key(&mut key);
+    assert_eq!(n, 48);
+    assert_eq!(key[..n], key_rx.await.unwrap());
+    // truncated to the buffer
+    let mut short = [0; 16];
+    assert_eq!(stream.master_key(&mut short), 16);
+    assert_eq!(short, key[..16]);
+    assert_eq!(stream.master_key(&mut []), 0);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn peek_client_hello_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    set_alpn_select(&mut builder, |offered| offered.first().copied());
+    let plain_context = builder.build().into_context();
+    let mut builder = test_acceptor_builder();
+    set_alpn_select(&mut builder, |offered| offered.first().copied());
+    enable_client_hello_peek(&mut builder);
+    let tls_context = builder.build().into_context();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(&tls_context, stream).unwrap();
+        let ClientHelloInfo {
+            server_name,
+            alpn_protocols,
+            versions,
+        } = stream.peek_client_hello().await.unwrap();
+        assert_eq!(server_name.as_deref(), Some("localhost"));
+        assert_eq!(alpn_protocols, [&b"h2"[..], b"http/1.1"]);
+        assert_eq!(versions[..2], [0x0304, 0x0303]);
+        // nothing was answered yet
+        assert_eq!(stream.raw_bytes_written(), 0);
+        stream.accept().await.unwrap();
+        assert_eq!(stream.selected_alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(
+            stream.ssl().servername(openssl::ssl::NameType::HOST_NAME),
+            Some("localhost")
+        );
+        stream.shutdown().await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(&tls_context, stream).unwrap();
+        let info = stream.peek_client_hello().await.unwrap();
+        assert_eq!(info.server_name.as_deref(), Some("unknown.example"));
+        assert!(info.alpn_protocols.is_empty());
+        stream.send_alert(Alert::new(112)).await.unwrap();
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = SslStream::from_context(&plain_context, stream).unwrap();
+        let err = stream.peek_client_hello().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    });
+
+    let tls_connector = test_connector_builder().build();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    tls_ctx.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap();
+    assert_eq!(stream.selected_alpn_protocol(), Some(&b"h2"[..]));
+    stream.shutdown().await.unwrap();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("unknown.example").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap_err();
+    assert_eq!(
+        stream.last_alert().map(Alert::description),
+        Some("unrecognized_name")
+    );
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    stream.connect().await.unwrap_err();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn spawn_accept_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let mut handshakes = Vec::new();
+        for _ in 0..4 {
+            let (stream, _) = listener.accept().await.unwrap();
+            handshakes.push(tls_acceptor.spawn_accept(stream));
+        }
+        let mut accepted = 0;
+        for handshake in handshakes {
+            if let Ok(mut stream) = handshake.await.unwrap() {
+                stream.write_all(TEST_PAYLOAD).await.unwrap();
+                stream.shutdown().await.unwrap();
+                accepted += 1;
+            }
+        }
+        accepted
+    });
+
+    // a client stalling before its hello does not hold back the others
+    let stalled = TcpStream::connect(addr).await.unwrap();
+    let tls_connector = test_connector();
+    let clients: Vec<_> = (0..3)
+        .map(|_| {
+            let tls_connector = tls_connector.clone();
+            compio::runtime::spawn(async move {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                tls_connector.connect("localhost", stream).await.unwrap()
+            })
+        })
+        .collect();
+    let mut streams = Vec::new();
+    for client in clients {
+        streams.push(client.await.unwrap());
+    }
+    drop(stalled);
+
+    for mut stream in streams {
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+        stream.shutdown().await.unwrap();
+    }
+    assert_eq!(server_task.await.unwrap(), 3);
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn async_cert_callback_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::X509;
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    let loaded = Arc::new(Mutex::new(None::<(X509, PKey<Private>)>));
+    set_cert_callback(&mut builder, move |ssl| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        if let Some((cert, key)) = loaded.lock().unwrap().take() {
+            ssl.set_certificate(&cert)?;
+            ssl.set_private_key(&key)?;
+            return Ok(CertResponse::SUCCESS);
+        }
+        // looked up by some other thread, e.g. from a remote key store
+        let loaded = loaded.clone();
+        let (tx, rx) = futures_channel::oneshot::channel();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            let cert = X509::from_pem(&std::fs::read("./test/public.pem").unwrap()).unwrap();
+            let key = PKey::private_key_from_pem(&std::fs::read("./test/privkey.pem").unwrap()).unwrap();
+            *loaded.lock().unwrap() = Some((cert, key));
+            tx.send(()).unwrap();
+        });
+        Ok(pause_cert_lookup(ssl, async move { rx.await.unwrap() }))
+    });
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+    });
+
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("./test/public.pem").unwrap();
+    let tls_connector = TlsConnector::new(builder.build());
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    server_task.await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[compio::test]
+async fn write_record_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(&buf[..11], b"hello world");
+        assert_eq!(buf.len(), 11 + 16 * 1024);
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let writes = Rc::new(Cell::new(0));
+    let stream = CountingStream {
+        inner: stream,
+        reads: Rc::default(),
+        writes: writes.clone(),
+    };
+    let mut stream = TlsConnector::new(tls_connector).connect("localhost", stream).await.unwrap();
+    let before = writes.get();
+    assert_eq!(stream.write_record(b"hello").await.unwrap(), 5);
+    assert_eq!(stream.write_record(b" world").await.unwrap(), 6);
+    assert_eq!(writes.get(), before);
+    assert!(stream.pending_write_bytes() > 11);
+    stream.flush_write_buf().await.unwrap();
+    assert_eq!(writes.get(), before + 1);
+    assert_eq!(stream.pending_write_bytes(), 0);
+
+    // one record at most
+    let large = vec![0; 20 * 1024];
+    assert_eq!(stream.write_record(&large).await.unwrap(), 16 * 1024);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn verified_chain_len_test() {
+    // root, an intermediate and the leaf
+    let chain = cert_chain(1);
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+    builder.set_certificate(&chain[2].0).unwrap();
+    builder.set_private_key(&chain[2].1).unwrap();
+    builder.add_extra_chain_cert(chain[1].0.clone()).unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            // the client is not verified at all
+            assert_eq!(stream.verified_chain_len(), None);
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let mut store = X509StoreBuilder::new().unwrap();
+    store.add_cert(chain[0].0.clone()).unwrap();
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_verify_cert_store(store.build()).unwrap();
+    let trusting = TlsConnector::new(builder.build());
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = trusting.connect("localhost", stream).await.unwrap();
+    #[cfg(ossl110)]
+    assert_eq!(stream.verified_chain_len(), Some(3));
+    stream.read_to_end(Vec::new()).await.unwrap();
+
+    // verification runs, but fails
+    let untrusting = test_connector();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = untrusting.connect("localhost", stream).await.unwrap();
+    assert_eq!(stream.verified_chain_len(), None);
+    stream.read_to_end(Vec::new()).await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn middlebox_compat_test() {
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = tls_acceptor.accept(stream).await {
+                let _ = stream.shutdown().await;
+            }
+        }
+    });
+
+    let tls_connector = test_connector_builder().build();
+    for compat in [true, false] {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let stream = RecordingStream {
+            inner: stream,
+            written: written.clone(),
+        };
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.set_middlebox_compat(compat);
+        stream.connect().await.unwrap();
+        // count the dummy `ChangeCipherSpec` records
+        let written = written.borrow();
+        let mut records = &written[..];
+        let mut change_cipher_specs = 0;
+        while records.len() >= 5 {
+            if records[0] == 20 {
+                change_cipher_specs += 1;
+            }
+            records = &records[5 + u16::from_be_bytes([records[3], records[4]]) as usize..];
+        }
+        assert_eq!(change_cipher_specs, compat as usize);
+    }
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = CorruptingStream {
+        inner: stream,
+        corrupted: false,
+    };
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    let err = stream.connect().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("corrupted in transit"), "{err}");
+    assert_distinct_chain(&err);
+    drop(stream);
+    server_task.await.unwrap();
+
+    // a genuine protocol failure is not mistaken for one
+    let (listener, addr) = test_listener().await;
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        test_acceptor().accept(stream).await.unwrap_err();
+    });
+    let mut builder = test_connector_builder();
+    builder.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+    let tls_connector = builder.build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    let err = stream.connect().await.unwrap_err();
+    assert_ne!(err.kind(), ErrorKind::InvalidData);
+    assert!(!err.to_string().contains("corrupted"), "{err}");
+    server_task.await.unwrap();
+}
+
+#[cfg(feature = "sync-compat")]
+#[test]
+fn blocking_stream_test() {
+    use std::io::{Read, Write};
+
+    let runtime = compio::runtime::Runtime::new().unwrap();
+    runtime.enter(|| {
+        let (listener, addr) = runtime.block_on(test_listener());
+        let tls_acceptor = test_acceptor();
+
+        // driven while the client blocks
+        let server_task = compio::runtime::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            let (_, req) = stream.read_exact(Vec::with_capacity(4)).await.unwrap();
+            assert_eq!(req, b"ping");
+            stream.write_all(TEST_PAYLOAD).await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        let tls_connector = test_connector();
+        let stream = runtime
+            .block_on(async {
+                let stream = TcpStream::connect(addr).await.unwrap();
+                tls_connector.connect("localhost", stream).await
+            })
+            .unwrap();
+
+        let mut stream = stream.into_blocking();
+        stream.write_all(b"ping").unwrap();
+        stream.flush().unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+        runtime.block_on(server_task).unwrap();
+    });
+}
+
+#[compio::test]
+async fn hostname_flags_test() {
+    use openssl::x509::verify::X509CheckFlags;
+
+    // a self-signed wildcard certificate
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, "*.example.com").unwrap();
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("*.example.com")
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = builder.build();
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls_server()).unwrap();
+    builder.set_certificate(&cert).unwrap();
+    builder.set_private_key(&key).unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = tls_acceptor.accept(stream).await {
+                stream.shutdown().await.unwrap();
+            }
+        }
+    });
+
+    let mut store = X509StoreBuilder::new().unwrap();
+    store.add_cert(cert).unwrap();
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_verify_cert_store(store.build()).unwrap();
+    let tls_connector = builder.build();
+    for (flags, ok) in [
+        (X509CheckFlags::NO_WILDCARDS, false),
+        (X509CheckFlags::empty(), true),
+    ] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let tls_ctx = tls_connector.configure().unwrap().into_ssl("www.example.com").unwrap();
+        let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+        stream.ssl_mut().param_mut().set_host("www.example.com").unwrap();
+        stream.set_hostname_flags(flags);
+        let ret = stream.connect().await;
+        assert_eq!(ret.is_ok(), ok);
+        if ok {
+            stream.read_to_end(Vec::new()).await.unwrap();
+        } else {
+            assert_eq!(
+                stream.ssl().verify_result().as_raw(),
+                ffi::X509_V_ERR_HOSTNAME_MISMATCH
+            );
+        }
+    }
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn max_early_data_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    builder.set_max_early_data(512).unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        assert_eq!(stream.max_early_data(), 512);
+        stream.shutdown().await.unwrap();
+        listener.accept().await.unwrap();
+    });
+
+    let tls_connector = test_connector_builder().build();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+    // nothing to resume yet
+    assert_eq!(stream.max_early_data(), 0);
+    stream.connect().await.unwrap();
+    // receiving the session tickets
+    stream.read_to_end(Vec::new()).await.unwrap();
+    let session = stream.ssl().session().unwrap().to_owned();
+
+    // known before the handshake
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+    unsafe { tls_ctx.set_session(&session).unwrap() };
+    let stream = SslStream::new(tls_ctx, stream).unwrap();
+    assert_eq!(stream.max_early_data(), 512);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn prebuffered_handshake_test() {
+    use std::io::BufRead;
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor_builder().build();
+
+    let server_task = compio::runtime::spawn(async move {
+        // the whole ClientHello, then just a part of the record header
+        for prefix in [None, Some(3)] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = compio::io::compat::SyncStream::new(TricklingStream {
+                inner: stream,
+                reads: Rc::default(),
+            });
+            loop {
+                stream.fill_read_buf().await.unwrap();
+                let peeked = stream.fill_buf().unwrap();
+                // a handshake record
+                assert_eq!(peeked[0], 22);
+                match prefix {
+                    Some(len) if peeked.len() >= len => break,
+                    None if peeked.len() >= 5
+                        && peeked.len() >= 5 + u16::from_be_bytes([peeked[3], peeked[4]]) as usize =>
+                    {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let ssl = openssl::ssl::Ssl::new(tls_acceptor.context()).unwrap();
+            let mut stream = SslStream::from(openssl::ssl::SslStream::new(ssl, stream).unwrap());
+            stream.accept().await.unwrap();
+            stream.write_all(TEST_PAYLOAD).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let tls_connector = test_connector();
+    for _ in 0..2 {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+    }
+    server_task.await.unwrap();
+}
+
+#[test]
+fn check_version_test() {
+    check_version(0x3000_0020, 0x1010_1000, "early data").unwrap();
+    check_version(0x1010_1000, 0x1010_1000, "early data").unwrap();
+    let err = check_version(0x1010_0000, 0x1010_1000, "early data").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(
+        err.to_string().starts_with("early data requires OpenSSL 1.1.1 or later"),
+        "{err}"
+    );
+    let err = check_version(0x1010_1000, 0x3000_0000, "sending session tickets").unwrap_err();
+    assert!(
+        err.to_string().starts_with("sending session tickets requires OpenSSL 3.0.0 or later"),
+        "{err}"
+    );
+    // the patch release moved to lower bits in 3.0
+    check_version(0x3000_0020, 0x3000_0020, "some feature").unwrap();
+    let err = check_version(0x3000_0010, 0x3000_0020, "some feature").unwrap_err();
+    assert!(
+        err.to_string().starts_with("some feature requires OpenSSL 3.0.2 or later"),
+        "{err}"
+    );
+    let err = check_version(0x3000_0020, 0x3050_0060, "some feature").unwrap_err();
+    assert!(
+        err.to_string().starts_with("some feature requires OpenSSL 3.5.6 or later"),
+        "{err}"
+    );
+}
+
+#[compio::test]
+async fn copy_test() {
+    let content: Vec<u8> = TEST_PAYLOAD.iter().copied().cycle().take(1024 * 1024 + 7).collect();
+    let (mut stream, mut server) = tls_pair().await;
+
+    let expected = content.clone();
+    let server_task = compio::runtime::spawn(async move {
+        let mut received = Vec::new();
+        let copied = server.copy_to(&mut received).await.unwrap();
+        assert_eq!(copied, expected.len() as u64);
+        assert!(received == expected);
+        server.shutdown().await.unwrap();
+    });
+
+    let copied = stream.copy_from(&mut &content[..]).await.unwrap();
+    assert_eq!(copied, content.len() as u64);
+    stream.shutdown().await.unwrap();
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn resumption_kind_test() {
+    use ResumptionKind::{Full, SessionId, Ticket};
+
+    // the client's and the server's TLS 1.2 ticket support, and the expected handshakes
+    let cases = [
+        (SslVersion::TLS1_3, true, [Full, Ticket]),
+        (SslVersion::TLS1_2, true, [Full, Ticket]),
+        (SslVersion::TLS1_2, false, [Full, SessionId]),
+    ];
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    enable_session_cache(&mut builder, b"resumption_kind_test", 16).unwrap();
+    let tls_context = builder.build().into_context();
+
+    let server_task = compio::runtime::spawn(async move {
+        for (_, tickets, kinds) in cases {
+            for kind in kinds {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = SslStream::from_context(&tls_context, stream).unwrap();
+                if !tickets {
+                    stream.set_options(SslOptions::NO_TICKET);
+                }
+                stream.accept().await.unwrap();
+                assert_eq!(stream.resumption_kind(), kind);
+                stream.shutdown().await.unwrap();
+            }
+        }
+    });
+
+    for (version, tickets, kinds) in cases {
+        let mut builder = test_connector_builder();
+        builder.set_max_proto_version(Some(version)).unwrap();
+        if !tickets {
+            builder.set_options(SslOptions::NO_TICKET);
+        }
+        let tls_connector = builder.build();
+        let mut session: Option<openssl::ssl::SslSession> = None;
+        for kind in kinds {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut tls_ctx = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+            if let Some(session) = &session {
+                unsafe { tls_ctx.set_session(session).unwrap() };
+            }
+            let mut stream = SslStream::new(tls_ctx, stream).unwrap();
+            stream.connect().await.unwrap();
+            // receiving the TLS 1.3 session tickets
+            stream.read_to_end(Vec::new()).await.unwrap();
+            assert_eq!(stream.resumption_kind(), kind);
+            session = stream.ssl().session().map(ToOwned::to_owned);
+            // OpenSSL marks the session of an unclosed connection as not resumable
+            stream.shutdown().await.unwrap();
+        }
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn pem_builders_test() {
+    let cert_pem = std::fs::read("./test/public.pem").unwrap();
+    let key_pem = std::fs::read("./test/privkey.pem").unwrap();
+    // root, an intermediate and the leaf
+    let chain = cert_chain(1);
+    let chain_pem = [chain[2].0.to_pem().unwrap(), chain[1].0.to_pem().unwrap()].concat();
+    let chain_key_pem = chain[2].1.private_key_to_pem_pkcs8().unwrap();
+
+    assert!(acceptor_from_pem(b"", &key_pem).is_err());
+    assert!(acceptor_from_pem(&chain_pem, &key_pem).is_err());
+    assert!(connector_from_ca_pem(b"").is_err());
+
+    let (listener, addr) = test_listener().await;
+    let acceptors = [
+        acceptor_from_pem(&cert_pem, &key_pem).unwrap(),
+        acceptor_from_pem(&chain_pem, &chain_key_pem).unwrap(),
+    ];
+    let server_task = compio::runtime::spawn(async move {
+        for tls_acceptor in acceptors {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.write_all(TEST_PAYLOAD).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    // the self-signed certificate, then the root of the chain
+    let connectors = [
+        connector_from_ca_pem(&cert_pem).unwrap(),
+        connector_from_ca_pem(&chain[0].0.to_pem().unwrap()).unwrap(),
+    ];
+    for tls_connector in connectors {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, TEST_PAYLOAD);
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn clear_error_test() {
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        compio::time::sleep(Duration::from_millis(200)).await;
+        server.write_all(TEST_PAYLOAD).await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+
+    // a failure leaving the connection intact
+    let err = stream.set_max_fragment_length(1000).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    // an error left behind by unrelated code, which would fail the next read
+    X509::from_pem(b"garbage").unwrap_err().put();
+    assert_ne!(unsafe { ffi::ERR_peek_last_error() }, 0);
+    stream.clear_error();
+    assert_eq!(unsafe { ffi::ERR_peek_last_error() }, 0);
+
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn aligned_buffer_test() {
+    let (mut stream, mut server) = tls_pair().await;
+
+    let server_task = compio::runtime::spawn(async move {
+        let (_, buf) = server.read_exact(AlignedBuf::new().slice(..TEST_PAYLOAD.len())).await.unwrap();
+        let buf = buf.into_inner();
+        assert_eq!(buf.as_buf_ptr() as usize % 4096, 0);
+        assert_eq!(buf.as_slice(), TEST_PAYLOAD);
+        server.write_all(buf).await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+
+    stream.write_all(AlignedBuf::from_slice(TEST_PAYLOAD)).await.unwrap();
+    stream.flush().await.unwrap();
+    let (_, buf) = stream.read_exact(AlignedBuf::new().slice(..TEST_PAYLOAD.len())).await.unwrap();
+    assert_eq!(buf.into_inner().as_slice(), TEST_PAYLOAD);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn alpn_or_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    set_alpn_select(&mut builder, |offered| {
+        offered.iter().copied().find(|&p| p == b"h2")
+    });
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for expected in [b"h2".as_slice(), b"http/1.1"] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            assert_eq!(stream.alpn_or(b"http/1.1"), expected);
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    for (protos, expected) in [
+        (Some(b"\x02h2".as_slice()), b"h2".as_slice()),
+        (None, b"http/1.1"),
+    ] {
+        let mut builder = test_connector_builder();
+        if let Some(protos) = protos {
+            builder.set_alpn_protos(protos).unwrap();
+        }
+        let tls_connector = TlsConnector::new(builder.build());
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+        assert_eq!(stream.alpn_or(b"http/1.1"), expected);
+        stream.shutdown().await.unwrap();
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn read_ring_test() {
+    let (mut stream, mut server) = tls_pair().await;
+    let (tx, rx) = futures_channel::oneshot::channel();
+
+    let server_task = compio::runtime::spawn(async move {
+        // several records arriving in a single transport read
+        for chunk in TEST_PAYLOAD.chunks(500) {
+            server.write_record(chunk).await.unwrap();
+        }
+        server.flush_write_buf().await.unwrap();
+        rx.await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+
+    // leave the unconsumed data near the end of the allocation, so the new data wraps around
+    let mut ring = VecDeque::with_capacity(4096);
+    let capacity = ring.capacity();
+    ring.extend(std::iter::repeat_n(0xaa, capacity - 10));
+    ring.drain(..capacity - 20);
+    let n = stream.read_ring(&mut ring).await.unwrap();
+    assert_eq!(n, TEST_PAYLOAD.len());
+    assert_eq!(ring.capacity(), capacity);
+    assert!(!ring.as_slices().1.is_empty());
+    assert!(ring.iter().take(10).all(|&b| b == 0xaa));
+    assert!(ring.iter().skip(10).copied().eq(TEST_PAYLOAD.iter().copied()));
+
+    // a full ring takes nothing
+    ring.resize(capacity, 0);
+    assert_eq!(stream.read_ring(&mut ring).await.unwrap(), 0);
+
+    ring.clear();
+    tx.send(()).unwrap();
+    assert_eq!(stream.read_ring(&mut ring).await.unwrap(), 0);
+    assert!(ring.is_empty());
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn read_ring_error_test() {
+    let (listener, addr) = test_listener().await;
+    let mut builder = test_acceptor_builder();
+    // no tickets to read before the records
+    builder.set_num_tickets(0).unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        // two records in a single transport write, the second corrupted on its way
+        stream.write_record(&TEST_PAYLOAD[..100]).await.unwrap();
+        stream.write_record(&TEST_PAYLOAD[100..200]).await.unwrap();
+        stream.flush_write_buf().await.unwrap();
+    });
+
+    let tls_connector = test_connector();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = CorruptingStream {
+        inner: stream,
+        corrupted: true,
+    };
+    let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+    stream.get_mut().corrupted = false;
+    server_task.await.unwrap();
+
+    // the data before the error is returned first, the error by the next call
+    let mut ring = VecDeque::with_capacity(4096);
+    assert_eq!(stream.read_ring(&mut ring).await.unwrap(), 100);
+    assert!(ring.iter().copied().eq(TEST_PAYLOAD[..100].iter().copied()));
+    let err = stream.read_ring(&mut ring).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(ring.len(), 100);
+}
+
+#[compio::test]
+async fn read_ahead_test() {
+    let payload: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let expected = payload.clone();
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.write_all(payload.clone()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let tls_connector = test_connector_builder().build();
+    for read_ahead in [false, true] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let ssl = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        let mut stream = SslStream::new(ssl, stream).unwrap();
+        stream.set_read_ahead(read_ahead);
+        assert_eq!(stream.read_ahead(), read_ahead);
+        stream.connect().await.unwrap();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert!(buf == expected);
+    }
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl300)]
+#[compio::test]
+async fn async_verify_test() {
+    use std::collections::HashSet;
+    use std::io::{BufRead, BufReader, Read as _, Write as _};
+    use std::sync::Mutex;
+
+    use openssl::x509::{X509StoreContext, X509VerifyResult};
+
+    use super::set_ex_data;
+
+    /// The revocation status of the server's certificate, once the responder answered.
+    struct OcspVerdict(Arc<Mutex<Option<bool>>>);
+
+    // a mock OCSP responder, answering whether the certificate of a serial number is revoked
+    let revoked = Arc::new(Mutex::new(HashSet::<String>::new()));
+    let responder = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let responder_addr = responder.local_addr().unwrap();
+    let revoked_serials = revoked.clone();
+    std::thread::spawn(move || {
+        for stream in responder.incoming() {
+            let mut stream = stream.unwrap();
+            let mut serial = String::new();
+            BufReader::new(&stream).read_line(&mut serial).unwrap();
+            let status = revoked_serials.lock().unwrap().contains(serial.trim());
+            stream.write_all(&[status as u8]).unwrap();
+        }
+    });
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = tls_acceptor.accept(stream).await.unwrap();
+        stream.write_all(TEST_PAYLOAD).await.unwrap();
+        stream.shutdown().await.unwrap();
+        // rejected by the client
+        let (stream, _) = listener.accept().await.unwrap();
+        tls_acceptor.accept(stream).await.unwrap_err();
+    });
+
+    let calls = Arc::new(AtomicU64::new(0));
+    let counter = calls.clone();
+    let mut builder = SslConnector::builder(SslMethod::tls_client()).unwrap();
+    builder.set_ca_file("./test/public.pem").unwrap();
+    set_cert_verify_callback(&mut builder, move |ctx| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        if !ctx.verify_cert().unwrap() {
+            return false;
+        }
+        let ssl = ctx.ex_data(X509StoreContext::ssl_idx().unwrap()).unwrap();
+        let verdict = ex_data::<OcspVerdict>(ssl).unwrap().0.clone();
+        let status = *verdict.lock().unwrap();
+        match status {
+            Some(false) => true,
+            Some(true) => {
+                // SAFETY: a valid verification error code
+                ctx.set_error(unsafe { X509VerifyResult::from_raw(ffi::X509_V_ERR_CERT_REVOKED) });
+                false
+            }
+            None => {
+                let leaf = &ctx.chain().unwrap()[0];
+                let serial = leaf.serial_number().to_bn().unwrap().to_hex_str().unwrap().to_string();
+                let (tx, rx) = futures_channel::oneshot::channel();
+                std::thread::spawn(move || {
+                    let mut stream = std::net::TcpStream::connect(responder_addr).unwrap();
+                    writeln!(stream, "{serial}").unwrap();
+                    let mut status = [0];
+                    stream.read_exact(&mut status).unwrap();
+                    *verdict.lock().unwrap() = Some(status[0] != 0);
+                    tx.send(()).unwrap();
+                });
+                pause_verification(ctx, async move { rx.await.unwrap() })
+            }
+        }
+    });
+    let tls_connector = builder.build();
+
+    let connect = async |tls_connector: &SslConnector| {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut ssl = tls_connector.configure().unwrap().into_ssl("localhost").unwrap();
+        set_ex_data(&mut ssl, OcspVerdict(Arc::new(Mutex::new(None))));
+        let mut stream = SslStream::new(ssl, stream).unwrap();
+        let ret = stream.connect().await;
+        (ret, stream)
+    };
+
+    let (ret, mut stream) = connect(&tls_connector).await;
+    ret.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+
+    let cert = X509::from_pem(&std::fs::read("./test/public.pem").unwrap()).unwrap();
+    let serial = cert.serial_number().to_bn().unwrap().to_hex_str().unwrap().to_string();
+    revoked.lock().unwrap().insert(serial);
+    let (ret, stream) = connect(&tls_connector).await;
+    ret.unwrap_err();
+    assert_eq!(
+        stream.ssl().verify_result().as_raw(),
+        ffi::X509_V_ERR_CERT_REVOKED
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+    server_task.await.unwrap();
+}
+
+#[cfg(ossl111)]
+#[compio::test]
+async fn peer_key_exchange_test() {
+    use openssl::pkey::Id;
+
+    let (listener, addr) = test_listener().await;
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    set_test_cert(&mut builder);
+    // RSA key transport, without ephemeral key
+    builder.set_cipher_list("ECDHE-RSA-AES128-GCM-SHA256:AES128-GCM-SHA256").unwrap();
+    let tls_acceptor = TlsAcceptor::new(builder.build());
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    // (cipher, TLS 1.3 only, expected key type, expected signature type and digest)
+    let cases = [
+        (
+            "ECDHE-RSA-AES128-GCM-SHA256",
+            false,
+            Some(Id::EC),
+            Some((Nid::RSAENCRYPTION, Nid::SHA256)),
+        ),
+        ("AES128-GCM-SHA256", false, None, None),
+        ("", true, Some(Id::X25519), Some((Nid::RSASSAPSS, Nid::SHA256))),
+    ];
+    for (cipher, tls13, key_id, signature) in cases {
+        let mut builder = test_connector_builder();
+        if tls13 {
+            builder.set_min_proto_version(Some(SslVersion::TLS1_3)).unwrap();
+            builder.set_groups_list("X25519").unwrap();
+            builder.set_sigalgs_list("rsa_pss_rsae_sha256").unwrap();
+        } else {
+            builder.set_max_proto_version(Some(SslVersion::TLS1_2)).unwrap();
+            builder.set_cipher_list(cipher).unwrap();
+            builder.set_groups_list("P-256").unwrap();
+            builder.set_sigalgs_list("RSA+SHA256").unwrap();
+        }
+        let tls_connector = TlsConnector::new(builder.build());
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+
+        let key = stream.peer_tmp_key();
+        assert_eq!(key.as_ref().map(|k| k.id()), key_id);
+        if key_id == Some(Id::EC) {
+            let curve = key.unwrap().ec_key().unwrap().group().curve_name();
+            assert_eq!(curve, Some(Nid::X9_62_PRIME256V1));
+        }
+        assert_eq!(
+            stream.peer_signature_type().zip(stream.peer_signature_nid()),
+            signature
+        );
+        stream.shutdown().await.unwrap();
+    }
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn cancelled_read_test() {
+    use futures_util::FutureExt;
+
+    let (mut stream, mut server) = tls_pair().await;
+    let (tx, rx) = futures_channel::oneshot::channel::<()>();
+
+    let server_task = compio::runtime::spawn(async move {
+        server.write_all(TEST_PAYLOAD).await.unwrap();
+        server.flush().await.unwrap();
+        // the client is done once it drops the sender
+        let _ = rx.await;
+    });
+
+    // dropped before it was polled, nothing happened
+    drop(stream.read(Vec::with_capacity(16)));
+    let (_, buf) = stream.read_exact(Vec::with_capacity(TEST_PAYLOAD.len())).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+
+    // dropped while waiting for the transport
+    assert!(stream.read(Vec::with_capacity(16)).now_or_never().is_none());
+    let BufResult(ret, _) = stream.read(Vec::with_capacity(16)).await;
+    assert!(ret.unwrap_err().to_string().contains("must be dropped"));
+    let BufResult(ret, _) = stream.write(b"hello").await;
+    ret.unwrap_err();
+    stream.shutdown().await.unwrap_err();
+
+    drop(tx);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn tee_test() {
+    use futures_util::StreamExt;
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.write_all(TEST_PAYLOAD).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+    });
+
+    let tls_connector = test_connector();
+
+    // an observer logging the first bytes while the main task handles the stream
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+    let mut tee = stream.tee();
+    let observer = compio::runtime::spawn(async move {
+        let mut head = Vec::new();
+        while head.len() < 64 {
+            head.extend(tee.next().await.unwrap());
+        }
+        head.truncate(64);
+        head
+    });
+    let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+    assert_eq!(buf, TEST_PAYLOAD);
+    assert_eq!(observer.await.unwrap(), &TEST_PAYLOAD[..64]);
+
+    // an observer lagging behind sees the start, then the end of its stream
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+    let tee = stream.tee();
+    for _ in 0..100 {
+        stream.read_exact(Vec::with_capacity(10)).await.unwrap();
+    }
+    let chunks = tee.collect::<Vec<_>>().await;
+    assert_eq!(chunks.len(), 64);
+    assert_eq!(chunks.concat(), &TEST_PAYLOAD[..640]);
+    drop(stream);
+    server_task.await.unwrap();
+}
+
+#[compio::test]
+async fn rate_limit_test() {
+    const RATE: u64 = 4000;
+    let payload = TEST_PAYLOAD.repeat(3);
+    let expected = payload.clone();
+
+    let (listener, addr) = test_listener().await;
+    let tls_acceptor = test_acceptor();
+
+    let server_task = compio::runtime::spawn(async move {
+        let mut elapsed = Vec::new();
+        for write_limit in [None, Some(RATE)] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = tls_acceptor.accept(stream).await.unwrap();
+            stream.set_write_rate_limit(write_limit);
+            let start = Instant::now();
+            for chunk in payload.chunks(500) {
+                stream.write_all(chunk.to_vec()).await.unwrap();
+            }
+            elapsed.push(start.elapsed());
+            stream.shutdown().await.unwrap();
+        }
+        elapsed
+    });
+
+    let tls_connector = test_connector();
+    for read_limit in [Some(RATE), None] {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = tls_connector.connect("localhost", stream).await.unwrap();
+        stream.set_read_rate_limit(read_limit);
+        let start = Instant::now();
+        let (_, buf) = stream.read_to_end(Vec::new()).await.unwrap();
+        assert_eq!(buf, expected);
+        if read_limit.is_some() {
+            // the bucket starts with a second's worth, and the final read waits until the debt is paid off
+            let min = (expected.len() as u64 - RATE) as f64 / RATE as f64;
+            assert!(start.elapsed().as_secs_f64() >= min);
+        }
+    }
+
+    let elapsed = server_task.await.unwrap();
+    // the last chunk is written without waiting for its share
+    let last = (expected.len() - 1) % 500 + 1;
+    let min = (expected.len() - last - RATE as usize) as f64 / RATE as f64;
+    assert!(elapsed[0].as_secs_f64() < min);
+    assert!(elapsed[1].as_secs_f64() >= min);
+}
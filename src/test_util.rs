@@ -0,0 +1,31 @@
+use std::net::IpAddr;
+
+use openssl::pkey::{PKey, Private};
+use openssl::x509::X509;
+use openssl::x509::extension::SubjectAlternativeName;
+
+use crate::self_signed::self_signed;
+
+/// Generates a self-signed certificate for `cn` and its private key, for integration tests.
+///
+/// The key is a P-256 EC key, and the certificate is valid for a day. Besides the common name, `cn` is set as
+/// the subject alternative name, as a DNS name or an IP address, so hostname verification passes, e.g. with
+/// `localhost`. To trust it, a client adds the certificate to its store with
+/// [`cert_store_mut`](openssl::ssl::SslContextBuilder::cert_store_mut).
+///
+/// # Panics
+///
+/// Panics if OpenSSL fails to generate the key or certificate.
+pub fn generate_self_signed(cn: &str) -> (X509, PKey<Private>) {
+    self_signed(cn, 1, |builder| {
+        let mut san = SubjectAlternativeName::new();
+        if cn.parse::<IpAddr>().is_ok() {
+            san.ip(cn);
+        } else {
+            san.dns(cn);
+        }
+        let san = san.build(&builder.x509v3_context(None, None))?;
+        builder.append_extension(san)
+    })
+    .expect("failed to generate a self-signed certificate")
+}
@@ -0,0 +1,92 @@
+use std::io;
+use std::pin::Pin;
+
+use compio::BufResult;
+use compio::buf::{IoBuf, IoBufMut};
+use compio::io::{AsyncRead, AsyncWrite};
+
+use crate::SslStream;
+
+type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// An [`SslStream`] over a type-erased transport, e.g. to keep TCP and Unix socket streams in one collection.
+pub type BoxedSslStream = SslStream<Box<dyn AsyncTransport>>;
+
+/// A dyn compatible counterpart of [`AsyncRead`] and [`AsyncWrite`], implemented for every `'static`
+/// transport, e.g. compio's `TcpStream` and `UnixStream`.
+///
+/// `Box<dyn AsyncTransport>` implements both traits itself, so it can be used as the transport of an
+/// [`SslStream`], see [`BoxedSslStream`]. The transport has to be boxed before the stream is created, as the
+/// stream cannot change its transport type afterwards. Buffers are copied from and to owned `Vec`s on the
+/// way, as generic buffers cannot cross a trait object.
+pub trait AsyncTransport: 'static {
+    /// Like [`AsyncRead::read`].
+    fn read_dyn(&mut self, buf: Vec<u8>) -> LocalBoxFuture<'_, BufResult<usize, Vec<u8>>>;
+
+    /// Like [`AsyncWrite::write`].
+    fn write_dyn(&mut self, buf: Vec<u8>) -> LocalBoxFuture<'_, BufResult<usize, Vec<u8>>>;
+
+    /// Like [`AsyncWrite::flush`].
+    fn flush_dyn(&mut self) -> LocalBoxFuture<'_, io::Result<()>>;
+
+    /// Like [`AsyncWrite::shutdown`].
+    fn shutdown_dyn(&mut self) -> LocalBoxFuture<'_, io::Result<()>>;
+
+    /// Erases the type of the transport.
+    fn boxed(self) -> Box<dyn AsyncTransport>
+    where
+        Self: Sized,
+    {
+        Box::new(self)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + 'static> AsyncTransport for T {
+    fn read_dyn(&mut self, buf: Vec<u8>) -> LocalBoxFuture<'_, BufResult<usize, Vec<u8>>> {
+        Box::pin(self.read(buf))
+    }
+
+    fn write_dyn(&mut self, buf: Vec<u8>) -> LocalBoxFuture<'_, BufResult<usize, Vec<u8>>> {
+        Box::pin(self.write(buf))
+    }
+
+    fn flush_dyn(&mut self) -> LocalBoxFuture<'_, io::Result<()>> {
+        Box::pin(self.flush())
+    }
+
+    fn shutdown_dyn(&mut self) -> LocalBoxFuture<'_, io::Result<()>> {
+        Box::pin(self.shutdown())
+    }
+}
+
+// `Box<dyn AsyncTransport>` is an `AsyncTransport` itself through the blanket impl, so the methods below must
+// dereference to the trait object explicitly, or they would call themselves.
+impl AsyncRead for Box<dyn AsyncTransport> {
+    async fn read<B: IoBufMut>(&mut self, mut buf: B) -> BufResult<usize, B> {
+        let dst = buf.as_mut_slice();
+        let BufResult(ret, src) = (**self).read_dyn(Vec::with_capacity(dst.len())).await;
+        if let Ok(n) = ret {
+            for (d, s) in dst.iter_mut().zip(&src[..n]) {
+                d.write(*s);
+            }
+            // SAFETY: the length we just copied
+            unsafe { buf.set_buf_init(n) };
+        }
+        BufResult(ret, buf)
+    }
+}
+
+impl AsyncWrite for Box<dyn AsyncTransport> {
+    async fn write<T: IoBuf>(&mut self, buf: T) -> BufResult<usize, T> {
+        let BufResult(ret, _) = (**self).write_dyn(buf.as_slice().to_vec()).await;
+        BufResult(ret, buf)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        (**self).flush_dyn().await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        (**self).shutdown_dyn().await
+    }
+}
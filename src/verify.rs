@@ -0,0 +1,91 @@
+use std::ffi::{c_int, c_void};
+use std::sync::OnceLock;
+
+use foreign_types::ForeignTypeRef;
+use openssl::ex_data::Index;
+use openssl::ssl::{SslContext, SslContextBuilder, SslRef};
+use openssl::x509::X509StoreContextRef;
+
+use crate::{client_hello, ffi};
+
+type VerifyCallback = Box<dyn Fn(&mut X509StoreContextRef) -> bool + Send + Sync>;
+
+fn callback_index() -> Index<SslContext, VerifyCallback> {
+    static INDEX: OnceLock<Index<SslContext, VerifyCallback>> = OnceLock::new();
+    *INDEX.get_or_init(|| SslContext::new_ex_index().expect("failed to allocate ex data index"))
+}
+
+/// Registers a callback verifying the peer's certificate chain as a whole, in place of OpenSSL's built-in
+/// verification.
+///
+/// `callback` is given the chain sent by the peer and returns whether it is trusted. It usually starts with
+/// [`X509StoreContextRef::verify_cert`] to run the built-in verification, and checks more afterwards, e.g.
+/// revocation through [`X509StoreContextRef::chain`]. To reject the chain, it sets the reason with
+/// [`X509StoreContextRef::set_error`], e.g. `X509VerifyResult::from_raw(X509_V_ERR_CERT_REVOKED)`, and
+/// returns `false`. Unlike [`SslContextBuilder::set_verify_callback`], it runs once per handshake rather than
+/// once per certificate, and only if verification is enabled with [`SslContextBuilder::set_verify`].
+///
+/// A client can defer the decision to an asynchronous check, e.g. an online OCSP request, with
+/// [`pause_verification`].
+///
+/// Reference: [`SSL_CTX_set_cert_verify_callback`](https://docs.openssl.org/master/man3/SSL_CTX_set_cert_verify_callback/)
+pub fn set_cert_verify_callback<F>(builder: &mut SslContextBuilder, callback: F)
+where
+    F: Fn(&mut X509StoreContextRef) -> bool + Send + Sync + 'static,
+{
+    builder.set_ex_data(callback_index(), Box::new(callback) as VerifyCallback);
+    // SAFETY: the context is valid, and the callback only reads the closure stored in it
+    unsafe {
+        ffi::SSL_CTX_set_cert_verify_callback(builder.as_ptr(), Some(cert_verify_cb), std::ptr::null_mut())
+    };
+}
+
+/// Pauses the handshake from within a [`set_cert_verify_callback`] callback until `resume` completes.
+///
+/// The callback returns the value given back. For a client, this is `true`, and OpenSSL reports
+/// `SSL_ERROR_WANT_RETRY_VERIFY`: [`SslStream::connect`](crate::SslStream::connect) then awaits `resume` and
+/// retries the handshake, verifying the chain again, so the callback runs once more. By then `resume` should
+/// have stored its verdict where the callback finds it, e.g. in a value attached with
+/// [`set_ex_data`](crate::set_ex_data), for the callback to return it instead of pausing again.
+///
+/// Only clients can pause verification: for a server, this returns `false`, failing the verification.
+///
+/// Reference: [`SSL_set_retry_verify`](https://docs.openssl.org/master/man3/SSL_set_retry_verify/)
+pub fn pause_verification<F>(ctx: &mut X509StoreContextRef, resume: F) -> bool
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    // SAFETY: OpenSSL attaches the `SSL` being verified to the store context it passes to the callback
+    let ssl = unsafe {
+        let ssl = ffi::X509_STORE_CTX_get_ex_data(ctx.as_ptr(), ffi::SSL_get_ex_data_X509_STORE_CTX_idx());
+        SslRef::from_ptr_mut(ssl as *mut ffi::SSL)
+    };
+    // SAFETY: the `Ssl` is valid, and the control takes no pointer argument
+    let paused = unsafe {
+        ffi::SSL_ctrl(
+            ssl.as_ptr(),
+            ffi::SSL_CTRL_SET_RETRY_VERIFY,
+            0,
+            std::ptr::null_mut(),
+        ) > 0
+    };
+    if paused {
+        client_hello::set_resume(ssl, resume);
+    }
+    paused
+}
+
+unsafe extern "C" fn cert_verify_cb(ctx: *mut ffi::X509_STORE_CTX, _arg: *mut c_void) -> c_int {
+    // SAFETY: OpenSSL passes the valid store context of the verification, exclusively for the callback
+    let ctx = unsafe { X509StoreContextRef::from_ptr_mut(ctx) };
+    // SAFETY: OpenSSL attaches the `SSL` being verified to the store context
+    let ssl = unsafe {
+        let ssl = ffi::X509_STORE_CTX_get_ex_data(ctx.as_ptr(), ffi::SSL_get_ex_data_X509_STORE_CTX_idx());
+        SslRef::from_ptr(ssl as *mut ffi::SSL)
+    };
+    let ssl_ctx = ssl.ssl_context().to_owned();
+    let Some(callback) = ssl_ctx.ex_data(callback_index()) else {
+        return 0;
+    };
+    callback(ctx) as c_int
+}